@@ -1,33 +1,117 @@
+use std::collections::BTreeMap;
+
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    to_json_binary, Binary, Coin, CosmosMsg, Deps, DepsMut, Env, MessageInfo, Response, StdResult,
-    WasmMsg,
+    from_json, to_json_binary, Addr, BankMsg, Binary, Coin, CosmosMsg, Decimal, Decimal256, Deps,
+    DepsMut, Empty, Env, Event, MessageInfo, Order, Reply, Response, StdError, StdResult, Storage,
+    SubMsg, Uint128, Uint256, WasmMsg,
 };
-use cw2::set_contract_version;
+use cw2::{get_contract_version, set_contract_version};
+use cw_storage_plus::Bound;
+use semver::Version;
 
 use crate::error::ContractError;
+use crate::events::ActionEvent;
 use crate::msg::{
-    CancelTx, ExecuteMsg, ExternalExecuteMsg, InstantiateMsg, MigrateMsg, PalomaMsg, QueryMsg,
-    SendTx,
+    AllowedRecipientInfo, AllowlistResponse, Asset, AssetInfo, BalanceResponse, BountyCapInfo,
+    CancelTx, Cw20HookMsg, DepositInfo, ExecuteMsg, ExternalExecuteMsg, ExternalQueryMsg,
+    InstantiateMsg, MigrateMsg, PalomaMsg, PusdManagerInfo, PusdManagerWithdrawal, QueryMsg,
+    RateLimitResponse, ReferenceData, SendTx, SimulateSwapOperationsResponse, SudoMsg,
+    SwapOperation,
+};
+use crate::state::{
+    ActionKind, AllowedRecipient, DepositPool, DepositorShare, DistributionTarget,
+    OutgoingTransfer, PendingAllowlistDelay, PendingSwap, PendingTransfer, PendingWithdrawal,
+    PreApproval, PreApprovedAction, PriceGuard, PurchaseOrder, PurchaseRoute, PusdWithdrawal,
+    RateLimit, Schedule, State, SwapPurpose, SweepRoute, TransferStatus, UnresolvedTransfer,
+    UnresolvedWithdrawal, WithdrawalStatus, ALLOWED_RECIPIENTS, ALLOWLIST_DISABLED_FROM,
+    ALLOWLIST_ENABLED, BOUNTY_CAPS, BRIDGEABLE_DENOMS, CW20_TOKENS, DEPOSITOR_SHARES,
+    DEPOSIT_POOLS, DISTRIBUTION, KEEPERS, NATIVE_DENOMS, ORDERS, ORDER_COUNT, OUTGOING_TRANSFERS,
+    PENDING_SWAP, PENDING_TRANSFERS, PENDING_WITHDRAWALS, PRE_APPROVALS, PRE_APPROVAL_COUNT,
+    PRICE_GUARDS, PUSD_MANAGERS, PUSD_WITHDRAWALS, RATE_LIMITS, ROUTES, SCHEDULES, SCHEDULE_COUNT,
+    SPENT_DEPOSIT_POOLS, STATE, STATE_V0_1, SWEEP_ROUTES, UNRESOLVED_TRANSFERS,
+    UNRESOLVED_TRANSFER_COUNT, UNRESOLVED_WITHDRAWALS, UNRESOLVED_WITHDRAWAL_COUNT,
 };
-use crate::state::{State, STATE};
 
 // version info for migration info
 const CONTRACT_NAME: &str = "crates.io:token-purchaser-collector-cw";
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+// same limit as the astroport router
+const MAX_SWAP_OPERATIONS: usize = 50;
+
+// keeps the price guard's powers of ten within Uint256
+const MAX_DECIMALS: u8 = 18;
+
+const SWAP_REPLY_ID: u64 = 1;
+const SEND_TX_REPLY_ID: u64 = 2;
+const PUSD_WITHDRAW_REPLY_ID: u64 = 3;
+
+// pagination for list queries
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
+
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> StdResult<Response> {
+pub fn migrate(deps: DepsMut, env: Env, msg: MigrateMsg) -> Result<Response, ContractError> {
+    let stored = get_contract_version(deps.storage)?;
+    if stored.contract != CONTRACT_NAME {
+        return Err(ContractError::WrongContract {
+            expected: CONTRACT_NAME.to_string(),
+            actual: stored.contract,
+        });
+    }
+    let stored_version: Version = stored.version.parse()?;
+    let version: Version = CONTRACT_VERSION.parse()?;
+    if stored_version > version {
+        return Err(ContractError::CannotDowngrade {
+            stored: stored.version,
+            version: CONTRACT_VERSION.to_string(),
+        });
+    }
+    if stored_version < Version::new(0, 2, 0) {
+        migrate_state_to_v0_2(deps.storage)?;
+    }
+    let mut state = STATE.load(deps.storage)?;
+    if let Some(allowlist_delay) = msg.allowlist_delay {
+        state.allowlist_delay = allowlist_delay;
+    }
+    if let Some(pusd_manager) = msg.pusd_manager {
+        let pusd_manager = deps.api.addr_validate(&pusd_manager)?;
+        let denom = msg
+            .pusd_denom
+            .unwrap_or_else(|| "factory/".to_string() + pusd_manager.as_str() + "/upusd");
+        PUSD_MANAGERS.save(deps.storage, &pusd_manager, &denom)?;
+        state.pusd_manager = Some(pusd_manager);
+    }
+    STATE.save(deps.storage, &state)?;
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
-    Ok(Response::default())
+    Ok(Response::new().add_attributes(
+        ActionEvent::new("migrate", &env.contract.address)
+            .attribute("from_version", stored.version)
+            .attribute("to_version", CONTRACT_VERSION),
+    ))
+}
+
+/// Rewrites the 0.1.0 `State` in the 0.2.0 layout, starting the fields added since from
+/// their defaults: no allowlist delay, no default pusd_manager and not paused.
+fn migrate_state_to_v0_2(storage: &mut dyn Storage) -> StdResult<()> {
+    let legacy = STATE_V0_1.load(storage)?;
+    let state = State {
+        owners: legacy.owners,
+        allowlist_delay: 0,
+        pusd_manager: None,
+        paused: false,
+        pending_allowlist_delay: None,
+    };
+    STATE.save(storage, &state)
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
     deps: DepsMut,
     _env: Env,
-    _info: MessageInfo,
+    info: MessageInfo,
     msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
     let state = State {
@@ -36,19 +120,26 @@ pub fn instantiate(
             .iter()
             .map(|x| deps.api.addr_validate(x).unwrap())
             .collect(),
+        allowlist_delay: 0,
+        pusd_manager: None,
+        paused: false,
+        pending_allowlist_delay: None,
     };
     STATE.save(deps.storage, &state)?;
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
-    Ok(Response::new().add_attribute("action", "instantiate"))
+    Ok(Response::new().add_attributes(ActionEvent::new("instantiate", &info.sender)))
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn execute(
-    deps: DepsMut,
-    _env: Env,
+    mut deps: DepsMut,
+    env: Env,
     info: MessageInfo,
     msg: ExecuteMsg,
 ) -> Result<Response<PalomaMsg>, ContractError> {
+    if STATE.load(deps.storage)?.paused {
+        return Err(ContractError::Paused {});
+    }
     match msg {
         ExecuteMsg::Exchange {
             dex_router,
@@ -57,50 +148,298 @@ pub fn execute(
             to,
             max_spread,
             funds,
+            cw20_amount,
         } => {
             let state = STATE.load(deps.storage)?;
-            assert!(
-                state.owners.iter().any(|x| x == info.sender),
-                "Unauthorized"
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let mut event = ActionEvent::new("exchange", &info.sender);
+            if let Some(to) = &to {
+                event = event.recipient(to);
+            }
+            let (swap, offer) = execute_exchange(
+                deps,
+                &env,
+                dex_router,
+                operations,
+                minimum_receive,
+                to,
+                max_spread,
+                funds,
+                cw20_amount,
+            )?;
+            Ok(Response::new()
+                .add_message(swap)
+                .add_attributes(event.amount(offer)))
+        }
+        ExecuteMsg::Receive(cw20_msg) => {
+            if !CW20_TOKENS.has(deps.storage, &info.sender) {
+                return Err(ContractError::UnregisteredCw20Token {
+                    contract_addr: info.sender.to_string(),
+                });
+            }
+            let sender = deps.api.addr_validate(&cw20_msg.sender)?;
+            match from_json(&cw20_msg.msg)? {
+                Cw20HookMsg::Deposit {} => {
+                    record_deposit(deps.storage, &sender, info.sender.as_str(), cw20_msg.amount)?;
+                    Ok(Response::new().add_attributes(
+                        ActionEvent::new("receive_deposit", sender)
+                            .amount(format!("{}{}", cw20_msg.amount, info.sender))
+                            .attribute("token", info.sender),
+                    ))
+                }
+                Cw20HookMsg::Swap {
+                    dex_router,
+                    operations,
+                    minimum_receive,
+                    to,
+                    max_spread,
+                } => {
+                    let state = STATE.load(deps.storage)?;
+                    assert!(state.owners.contains(&sender), "Unauthorized");
+                    let offer = validate_cw20_route(&operations, cw20_msg.amount)?;
+                    if offer.info
+                        != (AssetInfo::Token {
+                            contract_addr: info.sender.clone(),
+                        })
+                    {
+                        return Err(ContractError::FundsMismatch {
+                            expected: offer.info.to_string(),
+                        });
+                    }
+                    record_outflow(
+                        deps.branch(),
+                        &env,
+                        &offer.info,
+                        offer.amount,
+                        Outflow::Purchase,
+                    )?;
+                    let mut event = ActionEvent::new("receive_swap", &sender)
+                        .amount(&offer)
+                        .attribute("token", &info.sender);
+                    if let Some(to) = &to {
+                        event = event.recipient(to);
+                    }
+                    Ok(Response::new()
+                        .add_message(swap_message(
+                            deps.as_ref(),
+                            &env,
+                            &dex_router,
+                            operations,
+                            offer,
+                            minimum_receive,
+                            to,
+                            max_spread,
+                        )?)
+                        .add_attributes(event))
+                }
+            }
+        }
+        ExecuteMsg::ExchangeSplit {
+            legs,
+            total_minimum_receive,
+            max_spread,
+        } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let Some(last_leg) = legs.last() else {
+                return Err(ContractError::EmptySplit {});
+            };
+            let ask_asset_info = last_leg
+                .operations
+                .last()
+                .ok_or(ContractError::EmptyRoute {})?
+                .ask_asset_info()
+                .clone();
+            let mut swaps = vec![];
+            let mut offered: BTreeMap<String, Uint128> = BTreeMap::new();
+            for (index, leg) in legs.into_iter().enumerate() {
+                let offer = route_offer(&leg.operations, leg.offer_amount)?;
+                validate_route(&leg.operations, std::slice::from_ref(&offer))?;
+                if leg.operations[leg.operations.len() - 1].ask_asset_info() != &ask_asset_info {
+                    return Err(ContractError::SplitAskMismatch { index });
+                }
+                *offered.entry(offer.denom.clone()).or_default() += offer.amount;
+                swaps.push(swap_message(
+                    deps.as_ref(),
+                    &env,
+                    &leg.dex_router,
+                    leg.operations,
+                    offer.into(),
+                    None,
+                    None,
+                    max_spread,
+                )?);
+            }
+            let event = ActionEvent::new("exchange_split", &info.sender).amount(
+                offered
+                    .iter()
+                    .map(|(denom, amount)| format!("{amount}{denom}"))
+                    .collect::<Vec<String>>()
+                    .join(","),
             );
+            // legs offering the same denom are attributed against a single balance
+            for (denom, amount) in offered {
+                record_outflow(
+                    deps.branch(),
+                    &env,
+                    &AssetInfo::NativeToken { denom },
+                    amount,
+                    Outflow::Purchase,
+                )?;
+            }
+            // only the last leg replies, once every leg has been swapped
+            let last_swap = swaps.pop().unwrap();
             Ok(Response::new()
-                .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
-                    contract_addr: dex_router.to_string(),
-                    msg: to_json_binary(&ExternalExecuteMsg::ExecuteSwapOperations {
-                        operations,
-                        minimum_receive,
-                        to,
-                        max_spread,
-                    })?,
-                    funds,
-                }))
-                .add_attribute("action", "exchange"))
+                .add_messages(swaps)
+                .add_submessage(measured_swap(
+                    deps,
+                    &env,
+                    last_swap,
+                    ask_asset_info,
+                    SwapPurpose::Split {
+                        total_minimum_receive,
+                    },
+                )?)
+                .add_attributes(event))
         }
-        ExecuteMsg::WithdrawPusd {
+        ExecuteMsg::PurchaseAndBridge {
+            dex_router,
+            operations,
+            minimum_receive,
+            max_spread,
+            funds,
+            recipient,
+            chain_reference_id,
+        } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            validate_route(&operations, &funds)?;
+            let ask_asset_info = operations[operations.len() - 1].ask_asset_info().clone();
+            let AssetInfo::NativeToken { denom } = &ask_asset_info else {
+                return Err(ContractError::NotBridgeable {
+                    asset: ask_asset_info.to_string(),
+                });
+            };
+            assert_bridgeable(deps.as_ref(), &chain_reference_id, denom)?;
+            let event = ActionEvent::new("purchase_and_bridge", &info.sender)
+                .chain_id(&chain_reference_id)
+                .amount(&funds[0])
+                .recipient(&recipient);
+            let offer: Asset = funds[0].clone().into();
+            record_outflow(
+                deps.branch(),
+                &env,
+                &offer.info,
+                offer.amount,
+                Outflow::Purchase,
+            )?;
+            let swap = swap_message(
+                deps.as_ref(),
+                &env,
+                &dex_router,
+                operations,
+                offer,
+                minimum_receive,
+                None,
+                max_spread,
+            )?;
+            Ok(Response::new()
+                .add_submessage(measured_swap(
+                    deps,
+                    &env,
+                    swap,
+                    ask_asset_info,
+                    SwapPurpose::Bridge {
+                        recipient,
+                        chain_reference_id,
+                    },
+                )?)
+                .add_attributes(event))
+        }
+        ExecuteMsg::PurchasePusdAndWithdraw {
+            dex_router,
+            operations,
+            minimum_receive,
+            max_spread,
+            funds,
             pusd_manager,
             chain_id,
             recipient,
-            amount,
         } => {
             let state = STATE.load(deps.storage)?;
-            assert!(
-                state.owners.iter().any(|x| x == info.sender),
-                "Unauthorized"
-            );
-            let pusd_denom: String = "factory/".to_string() + pusd_manager.as_str() + "/upusd";
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            validate_route(&operations, &funds)?;
+            let (pusd_manager, pusd_denom) = resolve_pusd_manager(deps.storage, pusd_manager)?;
+            let ask_asset_info = operations[operations.len() - 1].ask_asset_info().clone();
+            let pusd_asset_info = AssetInfo::NativeToken { denom: pusd_denom };
+            if ask_asset_info != pusd_asset_info {
+                return Err(ContractError::AskAssetMismatch {
+                    expected: pusd_asset_info.to_string(),
+                    actual: ask_asset_info.to_string(),
+                });
+            }
+            let event = ActionEvent::new("purchase_pusd_and_withdraw", &info.sender)
+                .chain_id(&chain_id)
+                .amount(&funds[0])
+                .recipient(&recipient)
+                .attribute("pusd_manager", &pusd_manager);
+            let offer: Asset = funds[0].clone().into();
+            record_outflow(
+                deps.branch(),
+                &env,
+                &offer.info,
+                offer.amount,
+                Outflow::Purchase,
+            )?;
+            let swap = swap_message(
+                deps.as_ref(),
+                &env,
+                &dex_router,
+                operations,
+                offer,
+                minimum_receive,
+                None,
+                max_spread,
+            )?;
             Ok(Response::new()
-                .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
-                    contract_addr: pusd_manager.to_string(),
-                    msg: to_json_binary(&ExternalExecuteMsg::Withdraw {
+                .add_submessage(measured_swap(
+                    deps,
+                    &env,
+                    swap,
+                    ask_asset_info,
+                    SwapPurpose::PusdWithdraw {
+                        pusd_manager,
                         chain_id,
                         recipient,
-                    })?,
-                    funds: vec![Coin {
-                        denom: pusd_denom,
-                        amount,
-                    }],
-                }))
-                .add_attribute("action", "withdraw_pusd"))
+                    },
+                )?)
+                .add_attributes(event))
+        }
+        ExecuteMsg::WithdrawPusd {
+            pusd_manager,
+            chain_id,
+            recipient,
+            amount,
+        } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let (pusd_manager, pusd_denom) = resolve_pusd_manager(deps.storage, pusd_manager)?;
+            let event = ActionEvent::new("withdraw_pusd", &info.sender)
+                .chain_id(&chain_id)
+                .amount(Coin::new(amount, &pusd_denom))
+                .recipient(&recipient)
+                .attribute("pusd_manager", &pusd_manager);
+            Ok(Response::new()
+                .add_submessage(execute_withdraw_pusd(
+                    deps,
+                    &env,
+                    &pusd_manager,
+                    pusd_denom,
+                    chain_id,
+                    recipient,
+                    amount,
+                )?)
+                .add_attributes(event))
         }
 
         ExecuteMsg::ReWithdrawPusd {
@@ -108,17 +447,25 @@ pub fn execute(
             nonce,
         } => {
             let state = STATE.load(deps.storage)?;
-            assert!(
-                state.owners.iter().any(|x| x == info.sender),
-                "Unauthorized"
-            );
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let (pusd_manager, _) = resolve_pusd_manager(deps.storage, pusd_manager)?;
+            let withdrawal = PUSD_WITHDRAWALS
+                .may_load(deps.storage, (&pusd_manager, nonce))?
+                .ok_or(ContractError::UnknownWithdrawal { nonce })?;
+            if withdrawal.status != WithdrawalStatus::Pending {
+                return Err(ContractError::WithdrawalNotPending { nonce });
+            }
             Ok(Response::new()
                 .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
                     contract_addr: pusd_manager.to_string(),
                     msg: to_json_binary(&ExternalExecuteMsg::ReWithdraw { nonce })?,
                     funds: vec![],
                 }))
-                .add_attribute("action", "re_withdraw_pusd"))
+                .add_attributes(
+                    ActionEvent::new("re_withdraw_pusd", &info.sender)
+                        .nonce(nonce)
+                        .attribute("pusd_manager", pusd_manager),
+                ))
         }
 
         ExecuteMsg::CancelWithdrawPusd {
@@ -126,17 +473,110 @@ pub fn execute(
             nonce,
         } => {
             let state = STATE.load(deps.storage)?;
-            assert!(
-                state.owners.iter().any(|x| x == info.sender),
-                "Unauthorized"
-            );
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let (pusd_manager, _) = resolve_pusd_manager(deps.storage, pusd_manager)?;
+            let mut withdrawal = PUSD_WITHDRAWALS
+                .may_load(deps.storage, (&pusd_manager, nonce))?
+                .ok_or(ContractError::UnknownWithdrawal { nonce })?;
+            if withdrawal.status != WithdrawalStatus::Pending {
+                return Err(ContractError::WithdrawalNotPending { nonce });
+            }
+            withdrawal.status = WithdrawalStatus::Cancelled;
+            PUSD_WITHDRAWALS.save(deps.storage, (&pusd_manager, nonce), &withdrawal)?;
             Ok(Response::new()
                 .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
                     contract_addr: pusd_manager.to_string(),
                     msg: to_json_binary(&ExternalExecuteMsg::CancelWithdraw { nonce })?,
                     funds: vec![],
                 }))
-                .add_attribute("action", "cancel_withdraw_pusd"))
+                .add_attributes(
+                    ActionEvent::new("cancel_withdraw_pusd", &info.sender)
+                        .nonce(nonce)
+                        .attribute("pusd_manager", pusd_manager),
+                ))
+        }
+
+        ExecuteMsg::CompleteWithdrawPusd {
+            pusd_manager,
+            nonce,
+        } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let (pusd_manager, _) = resolve_pusd_manager(deps.storage, pusd_manager)?;
+            let mut withdrawal = PUSD_WITHDRAWALS
+                .may_load(deps.storage, (&pusd_manager, nonce))?
+                .ok_or(ContractError::UnknownWithdrawal { nonce })?;
+            if withdrawal.status != WithdrawalStatus::Pending {
+                return Err(ContractError::WithdrawalNotPending { nonce });
+            }
+            withdrawal.status = WithdrawalStatus::Completed;
+            PUSD_WITHDRAWALS.save(deps.storage, (&pusd_manager, nonce), &withdrawal)?;
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("complete_withdraw_pusd", &info.sender)
+                    .nonce(nonce)
+                    .attribute("pusd_manager", pusd_manager),
+            ))
+        }
+
+        ExecuteMsg::RegisterWithdrawPusd {
+            pusd_manager,
+            nonce,
+            chain_id,
+            recipient,
+            amount,
+        } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let (pusd_manager, pusd_denom) = resolve_pusd_manager(deps.storage, pusd_manager)?;
+            record_pusd_withdrawal(
+                deps.storage,
+                PusdWithdrawal {
+                    pusd_manager: pusd_manager.clone(),
+                    nonce,
+                    chain_id: chain_id.clone(),
+                    recipient: recipient.clone(),
+                    amount,
+                    status: WithdrawalStatus::Pending,
+                },
+            )?;
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("register_withdraw_pusd", &info.sender)
+                    .chain_id(chain_id)
+                    .amount(Coin::new(amount, pusd_denom))
+                    .recipient(recipient)
+                    .nonce(nonce)
+                    .attribute("pusd_manager", pusd_manager),
+            ))
+        }
+
+        ExecuteMsg::ResolveWithdrawPusd { id, nonce } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let unresolved = UNRESOLVED_WITHDRAWALS
+                .may_load(deps.storage, id)?
+                .ok_or(ContractError::UnknownUnresolvedWithdrawal { id })?;
+            record_pusd_withdrawal(
+                deps.storage,
+                PusdWithdrawal {
+                    pusd_manager: unresolved.pusd_manager.clone(),
+                    nonce,
+                    chain_id: unresolved.chain_id.clone(),
+                    recipient: unresolved.recipient.clone(),
+                    amount: unresolved.amount,
+                    status: WithdrawalStatus::Pending,
+                },
+            )?;
+            UNRESOLVED_WITHDRAWALS.remove(deps.storage, id);
+            let pusd_denom = PUSD_MANAGERS.load(deps.storage, &unresolved.pusd_manager)?;
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("resolve_withdraw_pusd", &info.sender)
+                    .chain_id(unresolved.chain_id)
+                    .amount(Coin::new(unresolved.amount, pusd_denom))
+                    .recipient(unresolved.recipient)
+                    .nonce(nonce)
+                    .attribute("unresolved_id", id)
+                    .attribute("pusd_manager", unresolved.pusd_manager),
+            ))
         }
 
         ExecuteMsg::SendToEvm {
@@ -145,75 +585,4428 @@ pub fn execute(
             chain_reference_id,
         } => {
             let state = STATE.load(deps.storage)?;
-            assert!(
-                state.owners.iter().any(|x| x == info.sender),
-                "Unauthorized"
-            );
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let event = ActionEvent::new("send_to_evm", &info.sender)
+                .chain_id(&chain_reference_id)
+                .amount(&amount)
+                .recipient(&recipient);
             Ok(Response::new()
-                .add_message(CosmosMsg::Custom(PalomaMsg::SkywayMsg {
-                    send_tx: Some(SendTx {
-                        remote_chain_destination_address: recipient,
-                        amount,
-                        chain_reference_id,
-                    }),
-                    cancel_tx: None,
-                }))
-                .add_attribute("action", "send_to_evm"))
+                .add_submessage(execute_send_to_evm(
+                    deps,
+                    &env,
+                    recipient,
+                    amount,
+                    chain_reference_id,
+                )?)
+                .add_attributes(event))
+        }
+        ExecuteMsg::SetBridgeableDenom {
+            chain_reference_id,
+            denom,
+            bridgeable,
+        } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            if bridgeable {
+                BRIDGEABLE_DENOMS.save(deps.storage, (&chain_reference_id, &denom), &Empty {})?;
+            } else {
+                BRIDGEABLE_DENOMS.remove(deps.storage, (&chain_reference_id, &denom));
+            }
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("set_bridgeable_denom", &info.sender)
+                    .chain_id(chain_reference_id)
+                    .attribute("denom", denom)
+                    .attribute("bridgeable", bridgeable),
+            ))
         }
 
+        ExecuteMsg::SetNativeDenom { denom, registered } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            if registered {
+                NATIVE_DENOMS.save(deps.storage, &denom, &Empty {})?;
+            } else {
+                NATIVE_DENOMS.remove(deps.storage, &denom);
+            }
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("set_native_denom", &info.sender)
+                    .attribute("denom", denom)
+                    .attribute("registered", registered),
+            ))
+        }
+        ExecuteMsg::SetCw20Token {
+            contract_addr,
+            registered,
+        } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let contract_addr = deps.api.addr_validate(&contract_addr)?;
+            if registered {
+                CW20_TOKENS.save(deps.storage, &contract_addr, &Empty {})?;
+            } else {
+                CW20_TOKENS.remove(deps.storage, &contract_addr);
+            }
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("set_cw20_token", &info.sender)
+                    .attribute("contract_addr", contract_addr)
+                    .attribute("registered", registered),
+            ))
+        }
         ExecuteMsg::CancelTx { transaction_id } => {
             let state = STATE.load(deps.storage)?;
-            assert!(
-                state.owners.iter().any(|x| x == info.sender),
-                "Unauthorized"
-            );
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let mut transfer = OUTGOING_TRANSFERS
+                .may_load(deps.storage, transaction_id)?
+                .ok_or(ContractError::UnknownTransaction { transaction_id })?;
+            if transfer.status != TransferStatus::Pending {
+                return Err(ContractError::TransferNotPending { transaction_id });
+            }
+            transfer.status = TransferStatus::Cancelled;
+            OUTGOING_TRANSFERS.save(deps.storage, transaction_id, &transfer)?;
             Ok(Response::new()
                 .add_message(CosmosMsg::Custom(PalomaMsg::SkywayMsg {
                     send_tx: None,
                     cancel_tx: Some(CancelTx { transaction_id }),
                 }))
-                .add_attribute("action", "cancel_tx"))
+                .add_attributes(
+                    ActionEvent::new("cancel_tx", &info.sender)
+                        .attribute("transaction_id", transaction_id),
+                ))
+        }
+        ExecuteMsg::RegisterTransfer {
+            transaction_id,
+            chain_reference_id,
+            recipient,
+            amount,
+        } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            record_transfer(
+                deps.storage,
+                OutgoingTransfer {
+                    transaction_id,
+                    recipient: recipient.clone(),
+                    amount: amount.clone(),
+                    chain_reference_id: chain_reference_id.clone(),
+                    status: TransferStatus::Pending,
+                },
+            )?;
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("register_transfer", &info.sender)
+                    .chain_id(chain_reference_id)
+                    .amount(amount)
+                    .recipient(recipient)
+                    .attribute("transaction_id", transaction_id),
+            ))
+        }
+        ExecuteMsg::ResolveTransfer { id, transaction_id } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let unresolved = UNRESOLVED_TRANSFERS
+                .may_load(deps.storage, id)?
+                .ok_or(ContractError::UnknownUnresolvedTransfer { id })?;
+            record_transfer(
+                deps.storage,
+                OutgoingTransfer {
+                    transaction_id,
+                    recipient: unresolved.recipient.clone(),
+                    amount: unresolved.amount.clone(),
+                    chain_reference_id: unresolved.chain_reference_id.clone(),
+                    status: TransferStatus::Pending,
+                },
+            )?;
+            UNRESOLVED_TRANSFERS.remove(deps.storage, id);
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("resolve_transfer", &info.sender)
+                    .chain_id(unresolved.chain_reference_id)
+                    .amount(unresolved.amount)
+                    .recipient(unresolved.recipient)
+                    .attribute("unresolved_id", id)
+                    .attribute("transaction_id", transaction_id),
+            ))
         }
 
-        ExecuteMsg::AddOwner { owners } => {
+        ExecuteMsg::UpdateConfig { allowlist_delay } => {
             let mut state = STATE.load(deps.storage)?;
-            assert!(
-                state.owners.iter().any(|x| x == info.sender),
-                "Unauthorized"
-            );
-            for owner in owners.iter() {
-                let owner = deps.api.addr_validate(owner)?;
-                if !state.owners.iter().any(|x| x == owner) {
-                    state.owners.push(owner);
-                }
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            if let Some(allowlist_delay) = allowlist_delay {
+                update_allowlist_delay(&mut state, &env, allowlist_delay);
             }
             STATE.save(deps.storage, &state)?;
-            Ok(Response::new().add_attribute("action", "update_config"))
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("update_config", &info.sender)
+                    .attribute("allowlist_delay", state.allowlist_delay),
+            ))
         }
-        ExecuteMsg::RemoveOwner { owner } => {
+        ExecuteMsg::SetPusdManager {
+            pusd_manager,
+            denom,
+            is_default,
+        } => {
             let mut state = STATE.load(deps.storage)?;
-            assert!(
-                state.owners.iter().any(|x| x == info.sender),
-                "Unauthorized"
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let pusd_manager = deps.api.addr_validate(&pusd_manager)?;
+            let denom =
+                denom.unwrap_or_else(|| "factory/".to_string() + pusd_manager.as_str() + "/upusd");
+            PUSD_MANAGERS.save(deps.storage, &pusd_manager, &denom)?;
+            if is_default {
+                state.pusd_manager = Some(pusd_manager.clone());
+                STATE.save(deps.storage, &state)?;
+            }
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("set_pusd_manager", &info.sender)
+                    .attribute("pusd_manager", pusd_manager)
+                    .attribute("denom", denom)
+                    .attribute("is_default", is_default),
+            ))
+        }
+        ExecuteMsg::RemovePusdManager { pusd_manager } => {
+            let mut state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let pusd_manager = deps.api.addr_validate(&pusd_manager)?;
+            PUSD_MANAGERS.remove(deps.storage, &pusd_manager);
+            if state.pusd_manager.as_ref() == Some(&pusd_manager) {
+                state.pusd_manager = None;
+                STATE.save(deps.storage, &state)?;
+            }
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("remove_pusd_manager", &info.sender)
+                    .attribute("pusd_manager", pusd_manager),
+            ))
+        }
+        ExecuteMsg::SetAllowlistEnabled { chain_id, enabled } => {
+            let mut state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let mut event = ActionEvent::new("set_allowlist_enabled", &info.sender)
+                .chain_id(&chain_id)
+                .attribute("enabled", enabled);
+            let allowlist_delay = current_allowlist_delay(&mut state, &env);
+            STATE.save(deps.storage, &state)?;
+            if enabled {
+                ALLOWLIST_ENABLED.save(deps.storage, &chain_id, &true)?;
+                ALLOWLIST_DISABLED_FROM.remove(deps.storage, &chain_id);
+            } else if allowlist_delay == 0 {
+                ALLOWLIST_ENABLED.remove(deps.storage, &chain_id);
+                ALLOWLIST_DISABLED_FROM.remove(deps.storage, &chain_id);
+            } else if ALLOWLIST_ENABLED
+                .may_load(deps.storage, &chain_id)?
+                .unwrap_or_default()
+            {
+                // lifting the allowlist waits out the same delay as adding a recipient
+                let disabled_from = env.block.time.seconds() + allowlist_delay;
+                ALLOWLIST_DISABLED_FROM.save(deps.storage, &chain_id, &disabled_from)?;
+                event = event.attribute("disabled_from", disabled_from);
+            }
+            Ok(Response::new().add_attributes(event))
+        }
+        ExecuteMsg::AddAllowedRecipient {
+            chain_id,
+            recipient,
+        } => {
+            let mut state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let active_from = env.block.time.seconds() + current_allowlist_delay(&mut state, &env);
+            STATE.save(deps.storage, &state)?;
+            ALLOWED_RECIPIENTS.save(
+                deps.storage,
+                (&chain_id, &recipient.to_lowercase()),
+                &AllowedRecipient {
+                    added_at_height: env.block.height,
+                    active_from,
+                },
+            )?;
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("add_allowed_recipient", &info.sender)
+                    .chain_id(chain_id)
+                    .recipient(recipient)
+                    .attribute("active_from", active_from),
+            ))
+        }
+        ExecuteMsg::RemoveAllowedRecipient {
+            chain_id,
+            recipient,
+        } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            ALLOWED_RECIPIENTS.remove(deps.storage, (&chain_id, &recipient.to_lowercase()));
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("remove_allowed_recipient", &info.sender)
+                    .chain_id(chain_id)
+                    .recipient(recipient),
+            ))
+        }
+        ExecuteMsg::SetRateLimit {
+            chain_id,
+            denom,
+            max_amount,
+            window,
+        } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            save_rate_limit(deps.storage, &chain_id, &denom, max_amount, window, false)?;
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("set_rate_limit", &info.sender)
+                    .chain_id(chain_id)
+                    .attribute("denom", denom)
+                    .attribute("max_amount", max_amount)
+                    .attribute("window", window),
+            ))
+        }
+        ExecuteMsg::SetPriceGuard {
+            offer_asset_info,
+            ask_asset_info,
+            oracle,
+            base_symbol,
+            quote_symbol,
+            offer_decimals,
+            ask_decimals,
+            max_deviation,
+            max_staleness,
+        } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            if max_deviation >= Decimal::one() {
+                return Err(ContractError::InvalidMaxDeviation {});
+            }
+            for decimals in [offer_decimals, ask_decimals] {
+                if decimals > MAX_DECIMALS {
+                    return Err(ContractError::InvalidDecimals {
+                        decimals,
+                        max: MAX_DECIMALS,
+                    });
+                }
+            }
+            let event = ActionEvent::new("set_price_guard", &info.sender)
+                .attribute("offer_asset", &offer_asset_info)
+                .attribute("ask_asset", &ask_asset_info);
+            PRICE_GUARDS.save(
+                deps.storage,
+                (offer_asset_info.to_string(), ask_asset_info.to_string()),
+                &PriceGuard {
+                    oracle: deps.api.addr_validate(&oracle)?,
+                    base_symbol,
+                    quote_symbol,
+                    offer_decimals,
+                    ask_decimals,
+                    max_deviation,
+                    max_staleness,
+                },
+            )?;
+            Ok(Response::new().add_attributes(event))
+        }
+        ExecuteMsg::RemovePriceGuard {
+            offer_asset_info,
+            ask_asset_info,
+        } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            PRICE_GUARDS.remove(
+                deps.storage,
+                (offer_asset_info.to_string(), ask_asset_info.to_string()),
             );
-            let owner = deps.api.addr_validate(&owner)?;
-            assert!(
-                state.owners.iter().any(|x| x == owner),
-                "Owner does not exist"
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("remove_price_guard", &info.sender)
+                    .attribute("offer_asset", offer_asset_info)
+                    .attribute("ask_asset", ask_asset_info),
+            ))
+        }
+        ExecuteMsg::SetRoute {
+            route_id,
+            dex_router,
+            operations,
+            max_spread,
+        } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let offer_asset_info = validate_hops(&operations)?.offer_asset_info().clone();
+            let ask_asset_info = operations[operations.len() - 1].ask_asset_info().clone();
+            ROUTES.save(
+                deps.storage,
+                &route_id,
+                &PurchaseRoute {
+                    id: route_id.clone(),
+                    offer_asset_info,
+                    ask_asset_info,
+                    dex_router,
+                    operations,
+                    max_spread,
+                },
+            )?;
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("set_route", &info.sender).attribute("route_id", route_id),
+            ))
+        }
+        ExecuteMsg::RemoveRoute { route_id } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            ROUTES.remove(deps.storage, &route_id);
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("remove_route", &info.sender).attribute("route_id", route_id),
+            ))
+        }
+        ExecuteMsg::ExchangeByRoute { route_id, amount } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let route = ROUTES.may_load(deps.storage, &route_id)?.ok_or_else(|| {
+                ContractError::UnknownRoute {
+                    route_id: route_id.clone(),
+                }
+            })?;
+            let balance = query_asset_balance(
+                deps.as_ref(),
+                &route.offer_asset_info,
+                &env.contract.address,
+            )?;
+            if amount.is_zero() || balance < amount {
+                return Err(ContractError::InsufficientBalance {
+                    denom: route.offer_asset_info.to_string(),
+                    balance,
+                    required: amount,
+                });
+            }
+            attribute_outflow(
+                deps.storage,
+                &route.offer_asset_info.to_string(),
+                balance,
+                amount,
+                Outflow::Purchase,
+            )?;
+            let offer = Asset {
+                info: route.offer_asset_info,
+                amount,
+            };
+            let event = ActionEvent::new("exchange_by_route", &info.sender)
+                .amount(&offer)
+                .attribute("route_id", route_id);
+            Ok(Response::new()
+                .add_message(swap_message(
+                    deps.as_ref(),
+                    &env,
+                    &route.dex_router,
+                    route.operations,
+                    offer,
+                    None,
+                    None,
+                    route.max_spread,
+                )?)
+                .add_attributes(event))
+        }
+        ExecuteMsg::SetSweepRoute {
+            dex_router,
+            operations,
+            max_spread,
+        } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let offer_asset_info = validate_hops(&operations)?.offer_asset_info().clone();
+            let target = operations[operations.len() - 1].ask_asset_info().clone();
+            SWEEP_ROUTES.save(
+                deps.storage,
+                (target.to_string(), offer_asset_info.to_string()),
+                &SweepRoute {
+                    dex_router,
+                    operations,
+                    max_spread,
+                },
+            )?;
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("set_sweep_route", &info.sender)
+                    .attribute("offer_asset", offer_asset_info)
+                    .attribute("target", target),
+            ))
+        }
+        ExecuteMsg::RemoveSweepRoute {
+            offer_asset_info,
+            target,
+        } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            SWEEP_ROUTES.remove(
+                deps.storage,
+                (target.to_string(), offer_asset_info.to_string()),
             );
-            state.owners.retain(|x| x != owner);
-            STATE.save(deps.storage, &state)?;
-            Ok(Response::new().add_attribute("action", "update_config"))
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("remove_sweep_route", &info.sender)
+                    .attribute("offer_asset", offer_asset_info)
+                    .attribute("target", target),
+            ))
         }
-    }
-}
-
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
-    match msg {
-        QueryMsg::GetState {} => to_json_binary(&STATE.load(deps.storage)?),
-    }
-}
-
-#[cfg(test)]
-mod tests {}
+        ExecuteMsg::SweepDust { target, min_value } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let routes = SWEEP_ROUTES
+                .prefix(target.to_string())
+                .range(deps.storage, None, None, Order::Ascending)
+                .map(|item| item.map(|(_, route)| route))
+                .collect::<StdResult<Vec<SweepRoute>>>()?;
+            let mut response = Response::new();
+            let mut swept = 0;
+            for route in routes {
+                let offer_asset_info = route.operations[0].offer_asset_info().clone();
+                let balance =
+                    query_asset_balance(deps.as_ref(), &offer_asset_info, &env.contract.address)?;
+                if balance.is_zero() {
+                    continue;
+                }
+                // a route the router cannot simulate has nothing worth sweeping
+                let Ok(value) =
+                    simulate_swap(deps.as_ref(), &route.dex_router, balance, &route.operations)
+                else {
+                    continue;
+                };
+                if value < min_value {
+                    continue;
+                }
+                attribute_outflow(
+                    deps.storage,
+                    &offer_asset_info.to_string(),
+                    balance,
+                    balance,
+                    Outflow::Purchase,
+                )?;
+                response = response.add_message(swap_message(
+                    deps.as_ref(),
+                    &env,
+                    &route.dex_router,
+                    route.operations,
+                    Asset {
+                        info: offer_asset_info,
+                        amount: balance,
+                    },
+                    None,
+                    None,
+                    route.max_spread,
+                )?);
+                swept += 1;
+            }
+            Ok(response.add_attributes(
+                ActionEvent::new("sweep_dust", &info.sender)
+                    .attribute("target", target)
+                    .attribute("swept", swept),
+            ))
+        }
+        ExecuteMsg::CreateSchedule {
+            dex_router,
+            operations,
+            amount_per_interval,
+            interval,
+            start_time,
+            end_time,
+            max_spread,
+        } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            validate_route(&operations, std::slice::from_ref(&amount_per_interval))?;
+            if interval == 0 {
+                return Err(ContractError::InvalidSchedule {
+                    reason: "interval must be positive".to_string(),
+                });
+            }
+            let next_execution = start_time.unwrap_or(env.block.time.seconds());
+            if end_time <= next_execution {
+                return Err(ContractError::InvalidSchedule {
+                    reason: "end time must be after start time".to_string(),
+                });
+            }
+            let id = SCHEDULE_COUNT.may_load(deps.storage)?.unwrap_or_default() + 1;
+            let event = ActionEvent::new("create_schedule", &info.sender)
+                .amount(&amount_per_interval)
+                .attribute("schedule_id", id)
+                .attribute("interval", interval)
+                .attribute("end_time", end_time);
+            SCHEDULE_COUNT.save(deps.storage, &id)?;
+            SCHEDULES.save(
+                deps.storage,
+                id,
+                &Schedule {
+                    id,
+                    dex_router,
+                    operations,
+                    amount_per_interval,
+                    interval,
+                    next_execution,
+                    end_time,
+                    max_spread,
+                    tranches_executed: 0,
+                    total_offered: Uint128::zero(),
+                    total_received: Uint128::zero(),
+                },
+            )?;
+            Ok(Response::new().add_attributes(event))
+        }
+        ExecuteMsg::CancelSchedule { schedule_id } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            SCHEDULES.load(deps.storage, schedule_id)?;
+            SCHEDULES.remove(deps.storage, schedule_id);
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("cancel_schedule", &info.sender)
+                    .attribute("schedule_id", schedule_id),
+            ))
+        }
+        ExecuteMsg::ExecuteDue { schedule_id } => {
+            let mut schedule = SCHEDULES.load(deps.storage, schedule_id)?;
+            let now = env.block.time.seconds();
+            if now < schedule.next_execution {
+                return Err(ContractError::ScheduleNotDue {
+                    next_execution: schedule.next_execution,
+                });
+            }
+            if now > schedule.end_time {
+                return Err(ContractError::ScheduleEnded {
+                    end_time: schedule.end_time,
+                });
+            }
+            // missed tranches are skipped rather than executed back to back
+            let missed = (now - schedule.next_execution) / schedule.interval;
+            schedule.next_execution += schedule.interval * (missed + 1);
+            schedule.tranches_executed += 1;
+            schedule.total_offered += schedule.amount_per_interval.amount;
+            SCHEDULES.save(deps.storage, schedule_id, &schedule)?;
+            let ask_asset_info = schedule.operations[schedule.operations.len() - 1]
+                .ask_asset_info()
+                .clone();
+            let event = ActionEvent::new("execute_due", &info.sender)
+                .amount(&schedule.amount_per_interval)
+                .attribute("schedule_id", schedule_id);
+            let offer: Asset = schedule.amount_per_interval.into();
+            record_outflow(
+                deps.branch(),
+                &env,
+                &offer.info,
+                offer.amount,
+                Outflow::Purchase,
+            )?;
+            let swap = swap_message(
+                deps.as_ref(),
+                &env,
+                &schedule.dex_router,
+                schedule.operations,
+                offer,
+                None,
+                None,
+                schedule.max_spread,
+            )?;
+            Ok(Response::new()
+                .add_submessage(measured_swap(
+                    deps,
+                    &env,
+                    swap,
+                    ask_asset_info,
+                    SwapPurpose::Schedule { schedule_id },
+                )?)
+                .add_attributes(event))
+        }
+        ExecuteMsg::CreateOrder {
+            dex_router,
+            operations,
+            offer,
+            target_price,
+            expiry,
+            max_spread,
+        } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            validate_route(&operations, std::slice::from_ref(&offer))?;
+            if target_price.is_zero() {
+                return Err(ContractError::InvalidOrder {
+                    reason: "target price must be positive".to_string(),
+                });
+            }
+            if expiry <= env.block.time.seconds() {
+                return Err(ContractError::InvalidOrder {
+                    reason: "expiry must be in the future".to_string(),
+                });
+            }
+            let id = ORDER_COUNT.may_load(deps.storage)?.unwrap_or_default() + 1;
+            let event = ActionEvent::new("create_order", &info.sender)
+                .amount(&offer)
+                .attribute("order_id", id)
+                .attribute("target_price", target_price)
+                .attribute("expiry", expiry);
+            ORDER_COUNT.save(deps.storage, &id)?;
+            ORDERS.save(
+                deps.storage,
+                id,
+                &PurchaseOrder {
+                    id,
+                    dex_router,
+                    operations,
+                    offer,
+                    target_price,
+                    expiry,
+                    max_spread,
+                },
+            )?;
+            Ok(Response::new().add_attributes(event))
+        }
+        ExecuteMsg::CancelOrder { order_id } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            ORDERS.load(deps.storage, order_id)?;
+            ORDERS.remove(deps.storage, order_id);
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("cancel_order", &info.sender).attribute("order_id", order_id),
+            ))
+        }
+        ExecuteMsg::ExecuteOrder { order_id } => {
+            let order = ORDERS.load(deps.storage, order_id)?;
+            if env.block.time.seconds() > order.expiry {
+                return Err(ContractError::OrderExpired {
+                    expiry: order.expiry,
+                });
+            }
+            let simulated = simulate_swap(
+                deps.as_ref(),
+                &order.dex_router,
+                order.offer.amount,
+                &order.operations,
+            )?;
+            let price =
+                Decimal::checked_from_ratio(order.offer.amount, simulated).unwrap_or(Decimal::MAX);
+            if price > order.target_price {
+                return Err(ContractError::PriceAboveTarget {
+                    price,
+                    target_price: order.target_price,
+                });
+            }
+            // bounded by the simulated return, so it cannot overflow
+            let minimum_receive = order.offer.amount.div_floor(order.target_price);
+            ORDERS.remove(deps.storage, order_id);
+            let event = ActionEvent::new("execute_order", &info.sender)
+                .amount(&order.offer)
+                .attribute("order_id", order_id)
+                .attribute("price", price);
+            let offer: Asset = order.offer.into();
+            record_outflow(
+                deps.branch(),
+                &env,
+                &offer.info,
+                offer.amount,
+                Outflow::Purchase,
+            )?;
+            Ok(Response::new()
+                .add_message(swap_message(
+                    deps.as_ref(),
+                    &env,
+                    &order.dex_router,
+                    order.operations,
+                    offer,
+                    Some(minimum_receive),
+                    None,
+                    order.max_spread,
+                )?)
+                .add_attributes(event))
+        }
+        ExecuteMsg::PruneExpiredOrders { start_after, limit } => {
+            let now = env.block.time.seconds();
+            let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+            // only one page of orders is scanned, so the cost does not grow with the map
+            let scanned = ORDERS
+                .range(
+                    deps.storage,
+                    start_after.map(Bound::exclusive),
+                    None,
+                    Order::Ascending,
+                )
+                .take(limit)
+                .map(|item| item.map(|(id, order)| (id, now > order.expiry)))
+                .collect::<StdResult<Vec<(u64, bool)>>>()?;
+            let mut count = 0u64;
+            for (id, expired) in scanned.iter() {
+                if *expired {
+                    ORDERS.remove(deps.storage, *id);
+                    count += 1;
+                }
+            }
+            let mut event =
+                ActionEvent::new("prune_expired_orders", &info.sender).attribute("count", count);
+            if let Some((last_order_id, _)) = scanned.last() {
+                event = event.attribute("last_order_id", last_order_id);
+            }
+            Ok(Response::new().add_attributes(event))
+        }
+        ExecuteMsg::Deposit {} => {
+            if info.funds.is_empty() {
+                return Err(ContractError::EmptyDeposit {});
+            }
+            for coin in info.funds.iter() {
+                record_deposit(deps.storage, &info.sender, &coin.denom, coin.amount)?;
+            }
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("deposit", &info.sender).amount(
+                    info.funds
+                        .iter()
+                        .map(Coin::to_string)
+                        .collect::<Vec<String>>()
+                        .join(","),
+                ),
+            ))
+        }
+        ExecuteMsg::Withdraw { recipient, coins } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let recipient = deps.api.addr_validate(&recipient)?;
+            let event = ActionEvent::new("withdraw", &info.sender)
+                .amount(
+                    coins
+                        .iter()
+                        .map(Coin::to_string)
+                        .collect::<Vec<String>>()
+                        .join(","),
+                )
+                .recipient(&recipient);
+            let mut response = Response::new();
+            for coin in coins {
+                response = response.add_message(withdraw_asset(
+                    deps.branch(),
+                    &env,
+                    &recipient,
+                    coin.into(),
+                )?);
+            }
+            Ok(response.add_attributes(event))
+        }
+        ExecuteMsg::WithdrawAll { recipient, denoms } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let recipient = deps.api.addr_validate(&recipient)?;
+            let mut response = Response::new();
+            let mut withdrawn = vec![];
+            for denom in denoms {
+                let balance = deps.querier.query_balance(&env.contract.address, denom)?;
+                if balance.amount.is_zero() {
+                    continue;
+                }
+                withdrawn.push(balance.to_string());
+                response = response.add_message(withdraw_asset(
+                    deps.branch(),
+                    &env,
+                    &recipient,
+                    balance.into(),
+                )?);
+            }
+            Ok(response.add_attributes(
+                ActionEvent::new("withdraw_all", &info.sender)
+                    .amount(withdrawn.join(","))
+                    .recipient(recipient),
+            ))
+        }
+        ExecuteMsg::WithdrawCw20 {
+            recipient,
+            token,
+            amount,
+        } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let recipient = deps.api.addr_validate(&recipient)?;
+            let token = deps.api.addr_validate(&token)?;
+            let asset = Asset {
+                info: AssetInfo::Token {
+                    contract_addr: token,
+                },
+                amount,
+            };
+            let event = ActionEvent::new("withdraw_cw20", &info.sender)
+                .amount(&asset)
+                .recipient(&recipient);
+            Ok(Response::new()
+                .add_message(withdraw_asset(deps.branch(), &env, &recipient, asset)?)
+                .add_attributes(event))
+        }
+        ExecuteMsg::SetDistribution { shares } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let total: u32 = shares.iter().map(|share| share.weight_bps as u32).sum();
+            if !shares.is_empty() && total != 10000 {
+                return Err(ContractError::InvalidDistribution {
+                    reason: format!("weights sum to {total} bps instead of 10000"),
+                });
+            }
+            for share in shares.iter() {
+                if share.weight_bps == 0 {
+                    return Err(ContractError::InvalidDistribution {
+                        reason: "zero weight".to_string(),
+                    });
+                }
+                match &share.target {
+                    DistributionTarget::Native { address } => {
+                        deps.api.addr_validate(address.as_str())?;
+                        assert_allowed_recipient(
+                            deps.storage,
+                            &env,
+                            &env.block.chain_id,
+                            address.as_str(),
+                        )?;
+                    }
+                    DistributionTarget::Evm {
+                        recipient,
+                        chain_reference_id,
+                    } => {
+                        assert_allowed_recipient(
+                            deps.storage,
+                            &env,
+                            chain_reference_id,
+                            recipient,
+                        )?;
+                    }
+                    DistributionTarget::Pusd {
+                        pusd_manager,
+                        chain_id,
+                        recipient,
+                    } => {
+                        resolve_pusd_manager(deps.storage, Some(pusd_manager.clone()))?;
+                        assert_allowed_recipient(deps.storage, &env, chain_id, recipient)?;
+                    }
+                }
+            }
+            DISTRIBUTION.save(deps.storage, &shares)?;
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("set_distribution", &info.sender)
+                    .attribute("shares", shares.len()),
+            ))
+        }
+        ExecuteMsg::Distribute { denom } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let shares = DISTRIBUTION.may_load(deps.storage)?.unwrap_or_default();
+            if shares.is_empty() {
+                return Err(ContractError::InvalidDistribution {
+                    reason: "no distribution table".to_string(),
+                });
+            }
+            let balance = deps
+                .querier
+                .query_balance(&env.contract.address, &denom)?
+                .amount;
+            if balance.is_zero() {
+                return Err(ContractError::InsufficientBalance {
+                    denom,
+                    balance,
+                    required: Uint128::one(),
+                });
+            }
+            let mut response = Response::new();
+            let mut remaining = balance;
+            for (index, share) in shares.iter().enumerate() {
+                // the last share takes the rounding remainder
+                let amount = if index == shares.len() - 1 {
+                    remaining
+                } else {
+                    balance.multiply_ratio(share.weight_bps, 10000u128)
+                };
+                if amount.is_zero() {
+                    continue;
+                }
+                let outflow = match &share.target {
+                    DistributionTarget::Native { address } => {
+                        response = response.add_message(transfer_asset(
+                            deps.storage,
+                            &env,
+                            address,
+                            Coin::new(amount, &denom).into(),
+                        )?);
+                        Outflow::Withdrawal
+                    }
+                    DistributionTarget::Evm {
+                        recipient,
+                        chain_reference_id,
+                    } => {
+                        assert_bridgeable(deps.as_ref(), chain_reference_id, &denom)?;
+                        response = response.add_submessage(send_to_evm(
+                            deps.storage,
+                            &env,
+                            recipient.clone(),
+                            Coin {
+                                denom: denom.clone(),
+                                amount,
+                            },
+                            chain_reference_id.clone(),
+                        )?);
+                        Outflow::Bridge
+                    }
+                    DistributionTarget::Pusd {
+                        pusd_manager,
+                        chain_id,
+                        recipient,
+                    } => {
+                        let (pusd_manager, pusd_denom) =
+                            resolve_pusd_manager(deps.storage, Some(pusd_manager.clone()))?;
+                        if pusd_denom != denom {
+                            return Err(ContractError::InvalidDistribution {
+                                reason: format!(
+                                    "{pusd_manager} withdraws {pusd_denom}, not {denom}"
+                                ),
+                            });
+                        }
+                        response = response.add_submessage(withdraw_pusd(
+                            deps.storage,
+                            &env,
+                            &pusd_manager,
+                            chain_id.clone(),
+                            recipient.clone(),
+                            amount,
+                        )?);
+                        Outflow::Bridge
+                    }
+                };
+                attribute_outflow(deps.storage, &denom, remaining, amount, outflow)?;
+                remaining -= amount;
+            }
+            Ok(response.add_attributes(
+                ActionEvent::new("distribute", &info.sender).amount(Coin::new(balance, denom)),
+            ))
+        }
+        ExecuteMsg::PreApprove {
+            action,
+            not_before,
+            bounty,
+            permissionless,
+        } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            if let Some(bounty) = &bounty {
+                let cap = BOUNTY_CAPS
+                    .may_load(deps.storage, (action.kind().as_str(), &bounty.denom))?
+                    .unwrap_or_default();
+                if bounty.amount > cap {
+                    return Err(ContractError::BountyExceedsCap {
+                        bounty: bounty.to_string(),
+                        cap,
+                    });
+                }
+            }
+            let id = PRE_APPROVAL_COUNT
+                .may_load(deps.storage)?
+                .unwrap_or_default()
+                + 1;
+            let event = ActionEvent::new("pre_approve", &info.sender)
+                .attribute("pre_approval_id", id)
+                .attribute("kind", action.kind().as_str());
+            PRE_APPROVAL_COUNT.save(deps.storage, &id)?;
+            PRE_APPROVALS.save(
+                deps.storage,
+                id,
+                &PreApproval {
+                    id,
+                    action,
+                    not_before,
+                    bounty,
+                    permissionless,
+                },
+            )?;
+            Ok(Response::new().add_attributes(event))
+        }
+        ExecuteMsg::RevokePreApproval { id } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            PRE_APPROVALS.remove(deps.storage, id);
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("revoke_pre_approval", &info.sender)
+                    .attribute("pre_approval_id", id),
+            ))
+        }
+        ExecuteMsg::ExecutePreApproved { id } => {
+            let approval = PRE_APPROVALS
+                .may_load(deps.storage, id)?
+                .ok_or(ContractError::UnknownPreApproval { id })?;
+            let state = STATE.load(deps.storage)?;
+            assert!(
+                approval.permissionless
+                    || KEEPERS.has(deps.storage, &info.sender)
+                    || state.owners.contains(&info.sender),
+                "Unauthorized"
+            );
+            if env.block.time.seconds() < approval.not_before {
+                return Err(ContractError::PreApprovalNotReady {
+                    not_before: approval.not_before,
+                });
+            }
+            // each approval runs once
+            PRE_APPROVALS.remove(deps.storage, id);
+            let kind = approval.action.kind();
+            let mut event = ActionEvent::new("execute_pre_approved", &info.sender)
+                .attribute("pre_approval_id", id)
+                .attribute("kind", kind.as_str());
+            let mut response = match approval.action {
+                PreApprovedAction::Exchange {
+                    dex_router,
+                    operations,
+                    minimum_receive,
+                    to,
+                    max_spread,
+                    funds,
+                    cw20_amount,
+                } => {
+                    if let Some(to) = &to {
+                        event = event.recipient(to);
+                    }
+                    let (swap, offer) = execute_exchange(
+                        deps.branch(),
+                        &env,
+                        dex_router,
+                        operations,
+                        minimum_receive,
+                        to,
+                        max_spread,
+                        funds,
+                        cw20_amount,
+                    )?;
+                    event = event.amount(offer);
+                    Response::new().add_message(swap)
+                }
+                PreApprovedAction::SendToEvm {
+                    recipient,
+                    amount,
+                    chain_reference_id,
+                } => {
+                    event = event
+                        .chain_id(&chain_reference_id)
+                        .amount(&amount)
+                        .recipient(&recipient);
+                    Response::new().add_submessage(execute_send_to_evm(
+                        deps.branch(),
+                        &env,
+                        recipient,
+                        amount,
+                        chain_reference_id,
+                    )?)
+                }
+                PreApprovedAction::WithdrawPusd {
+                    pusd_manager,
+                    chain_id,
+                    recipient,
+                    amount,
+                } => {
+                    let (pusd_manager, pusd_denom) =
+                        resolve_pusd_manager(deps.storage, pusd_manager)?;
+                    event = event
+                        .chain_id(&chain_id)
+                        .amount(Coin::new(amount, &pusd_denom))
+                        .recipient(&recipient)
+                        .attribute("pusd_manager", &pusd_manager);
+                    Response::new().add_submessage(execute_withdraw_pusd(
+                        deps.branch(),
+                        &env,
+                        &pusd_manager,
+                        pusd_denom,
+                        chain_id,
+                        recipient,
+                        amount,
+                    )?)
+                }
+            };
+            if let Some(mut bounty) = approval.bounty {
+                // caps lowered after the approval still apply
+                let cap = BOUNTY_CAPS
+                    .may_load(deps.storage, (kind.as_str(), &bounty.denom))?
+                    .unwrap_or_default();
+                bounty.amount = bounty.amount.min(cap);
+                if !bounty.amount.is_zero() {
+                    record_outflow(
+                        deps.branch(),
+                        &env,
+                        &AssetInfo::NativeToken {
+                            denom: bounty.denom.clone(),
+                        },
+                        bounty.amount,
+                        Outflow::Withdrawal,
+                    )?;
+                    event = event.attribute("bounty", &bounty);
+                    response = response.add_message(BankMsg::Send {
+                        to_address: info.sender.to_string(),
+                        amount: vec![bounty],
+                    });
+                }
+            }
+            Ok(response.add_attributes(event))
+        }
+        ExecuteMsg::SetKeeper { keeper, enabled } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let keeper = deps.api.addr_validate(&keeper)?;
+            if enabled {
+                KEEPERS.save(deps.storage, &keeper, &Empty {})?;
+            } else {
+                KEEPERS.remove(deps.storage, &keeper);
+            }
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("set_keeper", &info.sender)
+                    .attribute("keeper", keeper)
+                    .attribute("enabled", enabled),
+            ))
+        }
+        ExecuteMsg::SetBountyCap { kind, denom, cap } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let mut event = ActionEvent::new("set_bounty_cap", &info.sender)
+                .attribute("kind", kind.as_str())
+                .attribute("denom", &denom);
+            match cap {
+                Some(cap) => {
+                    BOUNTY_CAPS.save(deps.storage, (kind.as_str(), &denom), &cap)?;
+                    event = event.attribute("cap", cap);
+                }
+                None => BOUNTY_CAPS.remove(deps.storage, (kind.as_str(), &denom)),
+            }
+            Ok(Response::new().add_attributes(event))
+        }
+        ExecuteMsg::AddOwner { owners } => {
+            let mut state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            for owner in owners.iter() {
+                let owner = deps.api.addr_validate(owner)?;
+                if !state.owners.contains(&owner) {
+                    state.owners.push(owner);
+                }
+            }
+            STATE.save(deps.storage, &state)?;
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("add_owner", &info.sender).attribute("owners", owners.join(",")),
+            ))
+        }
+        ExecuteMsg::RemoveOwner { owner } => {
+            let mut state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let owner = deps.api.addr_validate(&owner)?;
+            assert!(state.owners.contains(&owner), "Owner does not exist");
+            state.owners.retain(|x| x != owner);
+            STATE.save(deps.storage, &state)?;
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("remove_owner", &info.sender).attribute("owner", owner),
+            ))
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn sudo(deps: DepsMut, env: Env, msg: SudoMsg) -> Result<Response<PalomaMsg>, ContractError> {
+    match msg {
+        SudoMsg::SetOwners { owners } => {
+            let mut state = STATE.load(deps.storage)?;
+            state.owners = owners
+                .iter()
+                .map(|owner| deps.api.addr_validate(owner))
+                .collect::<StdResult<Vec<Addr>>>()?;
+            STATE.save(deps.storage, &state)?;
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("sudo_set_owners", &env.contract.address)
+                    .attribute("owners", owners.join(",")),
+            ))
+        }
+        SudoMsg::Pause {} => {
+            let mut state = STATE.load(deps.storage)?;
+            state.paused = true;
+            STATE.save(deps.storage, &state)?;
+            Ok(Response::new()
+                .add_attributes(ActionEvent::new("sudo_pause", &env.contract.address)))
+        }
+        SudoMsg::Unpause {} => {
+            let mut state = STATE.load(deps.storage)?;
+            state.paused = false;
+            STATE.save(deps.storage, &state)?;
+            Ok(Response::new()
+                .add_attributes(ActionEvent::new("sudo_unpause", &env.contract.address)))
+        }
+        SudoMsg::CancelTx { transaction_id } => {
+            // transfers the contract did not record can be cancelled too
+            if let Some(mut transfer) = OUTGOING_TRANSFERS.may_load(deps.storage, transaction_id)? {
+                transfer.status = TransferStatus::Cancelled;
+                OUTGOING_TRANSFERS.save(deps.storage, transaction_id, &transfer)?;
+            }
+            Ok(Response::new()
+                .add_message(CosmosMsg::Custom(PalomaMsg::SkywayMsg {
+                    send_tx: None,
+                    cancel_tx: Some(CancelTx { transaction_id }),
+                }))
+                .add_attributes(
+                    ActionEvent::new("sudo_cancel_tx", &env.contract.address)
+                        .attribute("transaction_id", transaction_id),
+                ))
+        }
+        SudoMsg::CancelWithdrawPusd {
+            pusd_manager,
+            nonce,
+        } => {
+            // withdrawals the contract did not record can be cancelled too
+            if let Some(mut withdrawal) =
+                PUSD_WITHDRAWALS.may_load(deps.storage, (&pusd_manager, nonce))?
+            {
+                withdrawal.status = WithdrawalStatus::Cancelled;
+                PUSD_WITHDRAWALS.save(deps.storage, (&pusd_manager, nonce), &withdrawal)?;
+            }
+            Ok(Response::new()
+                .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: pusd_manager.to_string(),
+                    msg: to_json_binary(&ExternalExecuteMsg::CancelWithdraw { nonce })?,
+                    funds: vec![],
+                }))
+                .add_attributes(
+                    ActionEvent::new("sudo_cancel_withdraw_pusd", &env.contract.address)
+                        .nonce(nonce)
+                        .attribute("pusd_manager", pusd_manager),
+                ))
+        }
+        SudoMsg::SetRateLimit {
+            chain_id,
+            denom,
+            max_amount,
+            window,
+        } => {
+            save_rate_limit(deps.storage, &chain_id, &denom, max_amount, window, true)?;
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("sudo_set_rate_limit", &env.contract.address)
+                    .chain_id(chain_id)
+                    .attribute("denom", denom)
+                    .attribute("max_amount", max_amount)
+                    .attribute("window", window),
+            ))
+        }
+        SudoMsg::RemoveRateLimit { chain_id, denom } => {
+            RATE_LIMITS.remove(deps.storage, (&chain_id, &denom));
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("sudo_remove_rate_limit", &env.contract.address)
+                    .chain_id(chain_id)
+                    .attribute("denom", denom),
+            ))
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn reply(
+    mut deps: DepsMut,
+    env: Env,
+    msg: Reply,
+) -> Result<Response<PalomaMsg>, ContractError> {
+    match msg.id {
+        SWAP_REPLY_ID => {
+            let pending = PENDING_SWAP.load(deps.storage)?;
+            PENDING_SWAP.remove(deps.storage);
+            let received = query_asset_balance(
+                deps.as_ref(),
+                &pending.ask_asset_info,
+                &env.contract.address,
+            )?
+            .checked_sub(pending.balance_before)
+            .map_err(StdError::from)?;
+            let amount = Asset {
+                info: pending.ask_asset_info.clone(),
+                amount: received,
+            };
+            match pending.purpose {
+                SwapPurpose::Schedule { schedule_id } => {
+                    let mut schedule = SCHEDULES.load(deps.storage, schedule_id)?;
+                    schedule.total_received += received;
+                    SCHEDULES.save(deps.storage, schedule_id, &schedule)?;
+                    Ok(Response::new().add_attributes(
+                        ActionEvent::new("schedule_tranche_received", &env.contract.address)
+                            .amount(amount)
+                            .attribute("schedule_id", schedule_id),
+                    ))
+                }
+                SwapPurpose::Split {
+                    total_minimum_receive,
+                } => {
+                    if received < total_minimum_receive {
+                        return Err(ContractError::SplitMinimumNotMet {
+                            received,
+                            minimum: total_minimum_receive,
+                        });
+                    }
+                    Ok(Response::new().add_attributes(
+                        ActionEvent::new("exchange_split_received", &env.contract.address)
+                            .amount(amount),
+                    ))
+                }
+                SwapPurpose::Bridge {
+                    recipient,
+                    chain_reference_id,
+                } => {
+                    record_outflow(
+                        deps.branch(),
+                        &env,
+                        &pending.ask_asset_info,
+                        received,
+                        Outflow::Bridge,
+                    )?;
+                    let AssetInfo::NativeToken { denom } = pending.ask_asset_info else {
+                        return Err(ContractError::NotBridgeable {
+                            asset: pending.ask_asset_info.to_string(),
+                        });
+                    };
+                    Ok(Response::new()
+                        .add_submessage(send_to_evm(
+                            deps.storage,
+                            &env,
+                            recipient.clone(),
+                            Coin {
+                                denom,
+                                amount: received,
+                            },
+                            chain_reference_id.clone(),
+                        )?)
+                        .add_attributes(
+                            ActionEvent::new("purchase_and_bridge_received", &env.contract.address)
+                                .chain_id(chain_reference_id)
+                                .amount(amount)
+                                .recipient(recipient),
+                        ))
+                }
+                SwapPurpose::PusdWithdraw {
+                    pusd_manager,
+                    chain_id,
+                    recipient,
+                } => {
+                    record_outflow(
+                        deps.branch(),
+                        &env,
+                        &pending.ask_asset_info,
+                        received,
+                        Outflow::Bridge,
+                    )?;
+                    Ok(Response::new()
+                        .add_submessage(withdraw_pusd(
+                            deps.storage,
+                            &env,
+                            &pusd_manager,
+                            chain_id.clone(),
+                            recipient.clone(),
+                            received,
+                        )?)
+                        .add_attributes(
+                            ActionEvent::new(
+                                "purchase_pusd_and_withdraw_received",
+                                &env.contract.address,
+                            )
+                            .chain_id(chain_id)
+                            .amount(amount)
+                            .recipient(recipient)
+                            .attribute("pusd_manager", pusd_manager),
+                        ))
+                }
+            }
+        }
+        SEND_TX_REPLY_ID => {
+            let pending = PENDING_TRANSFERS
+                .pop_front(deps.storage)?
+                .ok_or_else(|| StdError::not_found("pending transfer"))?;
+            let response = msg.result.into_result().map_err(StdError::generic_err)?;
+            let Some(transaction_id) = parse_transaction_id(&response.events) else {
+                // the transfer went out, so keep it for an owner to resolve rather than
+                // reverting the whole outflow
+                let id = UNRESOLVED_TRANSFER_COUNT
+                    .may_load(deps.storage)?
+                    .unwrap_or(0)
+                    + 1;
+                UNRESOLVED_TRANSFER_COUNT.save(deps.storage, &id)?;
+                UNRESOLVED_TRANSFERS.save(
+                    deps.storage,
+                    id,
+                    &UnresolvedTransfer {
+                        id,
+                        recipient: pending.recipient.clone(),
+                        amount: pending.amount.clone(),
+                        chain_reference_id: pending.chain_reference_id.clone(),
+                    },
+                )?;
+                return Ok(Response::new().add_attributes(
+                    ActionEvent::new("send_to_evm_unresolved", &env.contract.address)
+                        .chain_id(pending.chain_reference_id)
+                        .amount(pending.amount)
+                        .recipient(pending.recipient)
+                        .attribute("unresolved_id", id),
+                ));
+            };
+            OUTGOING_TRANSFERS.save(
+                deps.storage,
+                transaction_id,
+                &OutgoingTransfer {
+                    transaction_id,
+                    recipient: pending.recipient.clone(),
+                    amount: pending.amount.clone(),
+                    chain_reference_id: pending.chain_reference_id.clone(),
+                    status: TransferStatus::Pending,
+                },
+            )?;
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("send_to_evm_recorded", &env.contract.address)
+                    .chain_id(pending.chain_reference_id)
+                    .amount(pending.amount)
+                    .recipient(pending.recipient)
+                    .attribute("transaction_id", transaction_id),
+            ))
+        }
+        PUSD_WITHDRAW_REPLY_ID => {
+            let pending = PENDING_WITHDRAWALS
+                .pop_front(deps.storage)?
+                .ok_or_else(|| StdError::not_found("pending withdrawal"))?;
+            let response = msg.result.into_result().map_err(StdError::generic_err)?;
+            let pusd_denom = PUSD_MANAGERS.load(deps.storage, &pending.pusd_manager)?;
+            let Some(nonce) = parse_withdrawal_nonce(&response.events, &pending.pusd_manager)
+            else {
+                // the withdrawal went out, so keep it for an owner to resolve rather than
+                // reverting it
+                let id = UNRESOLVED_WITHDRAWAL_COUNT
+                    .may_load(deps.storage)?
+                    .unwrap_or(0)
+                    + 1;
+                UNRESOLVED_WITHDRAWAL_COUNT.save(deps.storage, &id)?;
+                UNRESOLVED_WITHDRAWALS.save(
+                    deps.storage,
+                    id,
+                    &UnresolvedWithdrawal {
+                        id,
+                        pusd_manager: pending.pusd_manager.clone(),
+                        chain_id: pending.chain_id.clone(),
+                        recipient: pending.recipient.clone(),
+                        amount: pending.amount,
+                    },
+                )?;
+                return Ok(Response::new().add_attributes(
+                    ActionEvent::new("withdraw_pusd_unresolved", &env.contract.address)
+                        .chain_id(pending.chain_id)
+                        .amount(Coin::new(pending.amount, pusd_denom))
+                        .recipient(pending.recipient)
+                        .attribute("unresolved_id", id)
+                        .attribute("pusd_manager", pending.pusd_manager),
+                ));
+            };
+            PUSD_WITHDRAWALS.save(
+                deps.storage,
+                (&pending.pusd_manager, nonce),
+                &PusdWithdrawal {
+                    pusd_manager: pending.pusd_manager.clone(),
+                    nonce,
+                    chain_id: pending.chain_id.clone(),
+                    recipient: pending.recipient.clone(),
+                    amount: pending.amount,
+                    status: WithdrawalStatus::Pending,
+                },
+            )?;
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("withdraw_pusd_recorded", &env.contract.address)
+                    .chain_id(pending.chain_id)
+                    .amount(Coin::new(pending.amount, pusd_denom))
+                    .recipient(pending.recipient)
+                    .nonce(nonce)
+                    .attribute("pusd_manager", pending.pusd_manager),
+            ))
+        }
+        id => Err(ContractError::UnknownReplyId { id }),
+    }
+}
+
+/// Swaps funds held by the contract, spending either `funds` or `cw20_amount` of the
+/// route's first offer asset.
+#[allow(clippy::too_many_arguments)]
+fn execute_exchange(
+    mut deps: DepsMut,
+    env: &Env,
+    dex_router: Addr,
+    operations: Vec<SwapOperation>,
+    minimum_receive: Option<Uint128>,
+    to: Option<String>,
+    max_spread: Option<Decimal>,
+    funds: Vec<Coin>,
+    cw20_amount: Option<Uint128>,
+) -> Result<(CosmosMsg<PalomaMsg>, Asset), ContractError> {
+    let offer = match cw20_amount {
+        Some(amount) => {
+            if !funds.is_empty() {
+                return Err(ContractError::FundsMismatch {
+                    expected: "no funds".to_string(),
+                });
+            }
+            let offer = validate_cw20_route(&operations, amount)?;
+            let balance = query_asset_balance(deps.as_ref(), &offer.info, &env.contract.address)?;
+            if balance < amount {
+                return Err(ContractError::InsufficientBalance {
+                    denom: offer.info.to_string(),
+                    balance,
+                    required: amount,
+                });
+            }
+            offer
+        }
+        None => {
+            validate_route(&operations, &funds)?;
+            funds[0].clone().into()
+        }
+    };
+    record_outflow(
+        deps.branch(),
+        env,
+        &offer.info,
+        offer.amount,
+        Outflow::Purchase,
+    )?;
+    let swap = swap_message(
+        deps.as_ref(),
+        env,
+        &dex_router,
+        operations,
+        offer.clone(),
+        minimum_receive,
+        to,
+        max_spread,
+    )?;
+    Ok((swap, offer))
+}
+
+/// Sends `amount` held by the contract to an EVM `recipient` through Skyway.
+fn execute_send_to_evm(
+    mut deps: DepsMut,
+    env: &Env,
+    recipient: String,
+    amount: Coin,
+    chain_reference_id: String,
+) -> Result<SubMsg<PalomaMsg>, ContractError> {
+    assert_bridgeable(deps.as_ref(), &chain_reference_id, &amount.denom)?;
+    let balance = deps
+        .querier
+        .query_balance(&env.contract.address, &amount.denom)?;
+    if balance.amount < amount.amount {
+        return Err(ContractError::InsufficientBalance {
+            denom: amount.denom,
+            balance: balance.amount,
+            required: amount.amount,
+        });
+    }
+    record_outflow(
+        deps.branch(),
+        env,
+        &AssetInfo::NativeToken {
+            denom: amount.denom.clone(),
+        },
+        amount.amount,
+        Outflow::Bridge,
+    )?;
+    send_to_evm(deps.storage, env, recipient, amount, chain_reference_id)
+}
+
+/// Withdraws `amount` PUSD held by the contract to an EVM `recipient`.
+fn execute_withdraw_pusd(
+    mut deps: DepsMut,
+    env: &Env,
+    pusd_manager: &Addr,
+    pusd_denom: String,
+    chain_id: String,
+    recipient: String,
+    amount: Uint128,
+) -> Result<SubMsg<PalomaMsg>, ContractError> {
+    record_outflow(
+        deps.branch(),
+        env,
+        &AssetInfo::NativeToken { denom: pusd_denom },
+        amount,
+        Outflow::Bridge,
+    )?;
+    withdraw_pusd(deps.storage, env, pusd_manager, chain_id, recipient, amount)
+}
+
+/// Checks that `operations` form a connected route and that `funds` is exactly
+/// the offer asset of the first hop.
+fn validate_route(operations: &[SwapOperation], funds: &[Coin]) -> Result<(), ContractError> {
+    let first = validate_hops(operations)?;
+    match first.offer_asset_info() {
+        AssetInfo::NativeToken { denom }
+            if funds.len() == 1 && funds[0].denom == *denom && !funds[0].amount.is_zero() =>
+        {
+            Ok(())
+        }
+        offer_asset_info => Err(ContractError::FundsMismatch {
+            expected: offer_asset_info.to_string(),
+        }),
+    }
+}
+
+/// Validates a route spending `amount` of a CW20 token, which must be the route's first
+/// offer asset. Returns the offered asset.
+fn validate_cw20_route(
+    operations: &[SwapOperation],
+    amount: Uint128,
+) -> Result<Asset, ContractError> {
+    let first = validate_hops(operations)?;
+    match first.offer_asset_info() {
+        info @ AssetInfo::Token { .. } if !amount.is_zero() => Ok(Asset {
+            info: info.clone(),
+            amount,
+        }),
+        offer_asset_info => Err(ContractError::FundsMismatch {
+            expected: offer_asset_info.to_string(),
+        }),
+    }
+}
+
+/// Checks the route's length and that each hop offers what the previous one asked for.
+/// Returns the first hop.
+fn validate_hops(operations: &[SwapOperation]) -> Result<&SwapOperation, ContractError> {
+    let first = operations.first().ok_or(ContractError::EmptyRoute {})?;
+    if operations.len() > MAX_SWAP_OPERATIONS {
+        return Err(ContractError::TooManyHops {
+            hops: operations.len(),
+            max: MAX_SWAP_OPERATIONS,
+        });
+    }
+    for (index, hops) in operations.windows(2).enumerate() {
+        if hops[0].ask_asset_info() != hops[1].offer_asset_info() {
+            return Err(ContractError::BrokenRoute {
+                index: index + 1,
+                offer: hops[1].offer_asset_info().to_string(),
+                ask: hops[0].ask_asset_info().to_string(),
+            });
+        }
+    }
+    Ok(first)
+}
+
+/// Builds the router call swapping `offer` along `operations`. A CW20 offer is sent to the
+/// router with the swap as its receive hook. When a price guard is configured for the
+/// route, `minimum_receive` is raised to the oracle floor.
+#[allow(clippy::too_many_arguments)]
+fn swap_message(
+    deps: Deps,
+    env: &Env,
+    dex_router: &Addr,
+    operations: Vec<SwapOperation>,
+    offer: Asset,
+    minimum_receive: Option<Uint128>,
+    to: Option<String>,
+    max_spread: Option<Decimal>,
+) -> Result<CosmosMsg<PalomaMsg>, ContractError> {
+    let minimum_receive = match check_price_guard(deps, env, dex_router, &operations, offer.amount)?
+    {
+        Some(floor) => Some(minimum_receive.map_or(floor, |x| x.max(floor))),
+        None => minimum_receive,
+    };
+    let swap = to_json_binary(&ExternalExecuteMsg::ExecuteSwapOperations {
+        operations,
+        minimum_receive,
+        to,
+        max_spread,
+    })?;
+    Ok(match offer.info {
+        AssetInfo::NativeToken { denom } => CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: dex_router.to_string(),
+            msg: swap,
+            funds: vec![Coin {
+                denom,
+                amount: offer.amount,
+            }],
+        }),
+        AssetInfo::Token { contract_addr } => CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: contract_addr.to_string(),
+            msg: to_json_binary(&ExternalExecuteMsg::Send {
+                contract: dex_router.to_string(),
+                amount: offer.amount,
+                msg: swap,
+            })?,
+            funds: vec![],
+        }),
+    })
+}
+
+fn assert_bridgeable(
+    deps: Deps,
+    chain_reference_id: &str,
+    denom: &str,
+) -> Result<(), ContractError> {
+    if !BRIDGEABLE_DENOMS.has(deps.storage, (chain_reference_id, denom)) {
+        return Err(ContractError::DenomNotBridgeable {
+            denom: denom.to_string(),
+            chain_reference_id: chain_reference_id.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Builds the Skyway transfer of `amount` to an EVM `recipient`. The transfer is queued so
+/// that its reply can record it under the Skyway transaction id.
+fn send_to_evm(
+    storage: &mut dyn Storage,
+    env: &Env,
+    recipient: String,
+    amount: Coin,
+    chain_reference_id: String,
+) -> Result<SubMsg<PalomaMsg>, ContractError> {
+    assert_allowed_recipient(storage, env, &chain_reference_id, &recipient)?;
+    consume_rate_limit(
+        storage,
+        env,
+        &chain_reference_id,
+        &amount.denom,
+        amount.amount,
+    )?;
+    let send_tx = CosmosMsg::Custom(PalomaMsg::SkywayMsg {
+        send_tx: Some(SendTx {
+            remote_chain_destination_address: recipient.clone(),
+            amount: amount.to_string(),
+            chain_reference_id: chain_reference_id.clone(),
+        }),
+        cancel_tx: None,
+    });
+    PENDING_TRANSFERS.push_back(
+        storage,
+        &PendingTransfer {
+            recipient,
+            amount,
+            chain_reference_id,
+        },
+    )?;
+    Ok(SubMsg::reply_on_success(send_tx, SEND_TX_REPLY_ID))
+}
+
+/// Records a pending transfer under its transaction id, refusing to overwrite a recorded one.
+fn record_transfer(
+    storage: &mut dyn Storage,
+    transfer: OutgoingTransfer,
+) -> Result<(), ContractError> {
+    if OUTGOING_TRANSFERS.has(storage, transfer.transaction_id) {
+        return Err(ContractError::TransactionAlreadyRecorded {
+            transaction_id: transfer.transaction_id,
+        });
+    }
+    OUTGOING_TRANSFERS.save(storage, transfer.transaction_id, &transfer)?;
+    Ok(())
+}
+
+/// Finds the Skyway outgoing transaction id among the events of a `send_tx` reply.
+fn parse_transaction_id(events: &[Event]) -> Option<u64> {
+    events
+        .iter()
+        .flat_map(|event| {
+            event.attributes.iter().filter(|attribute| {
+                attribute.key == "outgoing_tx_id"
+                    || (event.ty.ends_with("EventOutgoingTxId") && attribute.key == "tx_id")
+            })
+        })
+        // typed events JSON-encode their values
+        .find_map(|attribute| attribute.value.trim_matches('"').parse().ok())
+}
+
+/// Builds the call withdrawing `amount` PUSD to an EVM `recipient` through a registered
+/// pusd_manager. The withdrawal is queued so that its reply can record it under its nonce.
+fn withdraw_pusd(
+    storage: &mut dyn Storage,
+    env: &Env,
+    pusd_manager: &Addr,
+    chain_id: String,
+    recipient: String,
+    amount: Uint128,
+) -> Result<SubMsg<PalomaMsg>, ContractError> {
+    let denom = PUSD_MANAGERS.load(storage, pusd_manager)?;
+    assert_allowed_recipient(storage, env, &chain_id, &recipient)?;
+    consume_rate_limit(storage, env, &chain_id, &denom, amount)?;
+    let withdraw = CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: pusd_manager.to_string(),
+        msg: to_json_binary(&ExternalExecuteMsg::Withdraw {
+            chain_id: chain_id.clone(),
+            recipient: recipient.clone(),
+        })?,
+        funds: vec![Coin { denom, amount }],
+    });
+    PENDING_WITHDRAWALS.push_back(
+        storage,
+        &PendingWithdrawal {
+            pusd_manager: pusd_manager.clone(),
+            chain_id,
+            recipient,
+            amount,
+        },
+    )?;
+    Ok(SubMsg::reply_on_success(withdraw, PUSD_WITHDRAW_REPLY_ID))
+}
+
+/// Finds the withdrawal nonce among the events `pusd_manager` emitted in a `withdraw` reply.
+fn parse_withdrawal_nonce(events: &[Event], pusd_manager: &Addr) -> Option<u64> {
+    events
+        .iter()
+        .filter(|event| {
+            event.ty.starts_with("wasm")
+                && event.attributes.iter().any(|attribute| {
+                    attribute.key == "_contract_address" && attribute.value == pusd_manager.as_str()
+                })
+        })
+        .flat_map(|event| event.attributes.iter())
+        .find(|attribute| attribute.key == "nonce")
+        .and_then(|attribute| attribute.value.parse().ok())
+}
+
+/// Records a pending withdrawal under its nonce, refusing to overwrite a recorded one.
+fn record_pusd_withdrawal(
+    storage: &mut dyn Storage,
+    withdrawal: PusdWithdrawal,
+) -> Result<(), ContractError> {
+    let key = (&withdrawal.pusd_manager, withdrawal.nonce);
+    if PUSD_WITHDRAWALS.has(storage, key) {
+        return Err(ContractError::WithdrawalAlreadyRecorded {
+            nonce: withdrawal.nonce,
+        });
+    }
+    PUSD_WITHDRAWALS.save(storage, key, &withdrawal)?;
+    Ok(())
+}
+
+/// Resolves the pusd_manager of a call to the default one when none is given. An explicit
+/// pusd_manager must be registered. Returns the pusd_manager with its PUSD denom.
+fn resolve_pusd_manager(
+    storage: &dyn Storage,
+    pusd_manager: Option<Addr>,
+) -> Result<(Addr, String), ContractError> {
+    let pusd_manager = match pusd_manager {
+        Some(pusd_manager) => pusd_manager,
+        None => STATE
+            .load(storage)?
+            .pusd_manager
+            .ok_or(ContractError::NoDefaultPusdManager {})?,
+    };
+    let denom = PUSD_MANAGERS.may_load(storage, &pusd_manager)?.ok_or(
+        ContractError::UnregisteredPusdManager {
+            pusd_manager: pusd_manager.to_string(),
+        },
+    )?;
+    Ok((pusd_manager, denom))
+}
+
+/// Kinds of outflow attributed to depositors.
+enum Outflow {
+    Purchase,
+    Bridge,
+    Withdrawal,
+}
+
+/// Records `amount` of `denom` deposited by `depositor`, minting shares of the denom's
+/// deposit pool at its current ratio.
+fn record_deposit(
+    storage: &mut dyn Storage,
+    depositor: &Addr,
+    denom: &str,
+    amount: Uint128,
+) -> StdResult<()> {
+    let mut pool = DEPOSIT_POOLS.may_load(storage, denom)?.unwrap_or_default();
+    let mut share = load_depositor_share(storage, depositor, denom, &pool)?;
+    let shares = if pool.shares.is_zero() {
+        amount
+    } else {
+        amount.multiply_ratio(pool.shares, pool.remaining)
+    };
+    pool.shares += shares;
+    pool.remaining += amount;
+    share.shares += shares;
+    share.deposited += amount;
+    DEPOSIT_POOLS.save(storage, denom, &pool)?;
+    DEPOSITOR_SHARES.save(storage, (depositor, denom), &share)
+}
+
+/// Loads the share of `depositor` in the deposit pool of `denom`, with everything
+/// attributed to it since its last update settled.
+fn load_depositor_share(
+    storage: &dyn Storage,
+    depositor: &Addr,
+    denom: &str,
+    pool: &DepositPool,
+) -> StdResult<DepositorShare> {
+    let Some(mut share) = DEPOSITOR_SHARES.may_load(storage, (depositor, denom))? else {
+        return Ok(DepositorShare {
+            generation: pool.generation,
+            purchased_checkpoint: pool.purchased_per_share,
+            bridged_checkpoint: pool.bridged_per_share,
+            withdrawn_checkpoint: pool.withdrawn_per_share,
+            ..DepositorShare::default()
+        });
+    };
+    // shares of an exhausted pool are settled against its final state and dropped
+    let settled = if share.generation == pool.generation {
+        pool.clone()
+    } else {
+        SPENT_DEPOSIT_POOLS.load(storage, (denom, share.generation))?
+    };
+    share.purchased += share
+        .shares
+        .mul_floor(settled.purchased_per_share - share.purchased_checkpoint);
+    share.bridged += share
+        .shares
+        .mul_floor(settled.bridged_per_share - share.bridged_checkpoint);
+    share.withdrawn += share
+        .shares
+        .mul_floor(settled.withdrawn_per_share - share.withdrawn_checkpoint);
+    if share.generation != pool.generation {
+        share.generation = pool.generation;
+        share.shares = Uint128::zero();
+    }
+    share.purchased_checkpoint = pool.purchased_per_share;
+    share.bridged_checkpoint = pool.bridged_per_share;
+    share.withdrawn_checkpoint = pool.withdrawn_per_share;
+    Ok(share)
+}
+
+/// Attributes an outflow of `amount` of `asset_info` to its depositors. Deposited tokens
+/// are taken to leave in proportion to their part of the contract's balance.
+fn record_outflow(
+    deps: DepsMut,
+    env: &Env,
+    asset_info: &AssetInfo,
+    amount: Uint128,
+    outflow: Outflow,
+) -> StdResult<()> {
+    let denom = asset_info.to_string();
+    if !DEPOSIT_POOLS.has(deps.storage, &denom) {
+        return Ok(());
+    }
+    let balance = query_asset_balance(deps.as_ref(), asset_info, &env.contract.address)?;
+    attribute_outflow(deps.storage, &denom, balance, amount, outflow)
+}
+
+/// Attributes an outflow of `amount` of `denom` out of a `balance` to its depositors.
+fn attribute_outflow(
+    storage: &mut dyn Storage,
+    denom: &str,
+    balance: Uint128,
+    amount: Uint128,
+    outflow: Outflow,
+) -> StdResult<()> {
+    let Some(mut pool) = DEPOSIT_POOLS.may_load(storage, denom)? else {
+        return Ok(());
+    };
+    if pool.shares.is_zero() || amount.is_zero() {
+        return Ok(());
+    }
+    let attributed = amount
+        .multiply_ratio(pool.remaining, balance.max(amount))
+        .min(pool.remaining);
+    let per_share = Decimal::from_ratio(attributed, pool.shares);
+    match outflow {
+        Outflow::Purchase => pool.purchased_per_share += per_share,
+        Outflow::Bridge => pool.bridged_per_share += per_share,
+        Outflow::Withdrawal => pool.withdrawn_per_share += per_share,
+    }
+    pool.remaining -= attributed;
+    if pool.remaining.is_zero() {
+        // every deposit is spent, so later deposits start a new pool
+        SPENT_DEPOSIT_POOLS.save(storage, (denom, pool.generation), &pool)?;
+        pool = DepositPool {
+            generation: pool.generation + 1,
+            ..DepositPool::default()
+        };
+    }
+    DEPOSIT_POOLS.save(storage, denom, &pool)
+}
+
+/// Builds the withdrawal of `asset` to a Paloma `recipient` and attributes it to depositors.
+fn withdraw_asset(
+    deps: DepsMut,
+    env: &Env,
+    recipient: &Addr,
+    asset: Asset,
+) -> Result<CosmosMsg<PalomaMsg>, ContractError> {
+    let denom = asset.info.to_string();
+    let balance = query_asset_balance(deps.as_ref(), &asset.info, &env.contract.address)?;
+    if balance < asset.amount {
+        return Err(ContractError::InsufficientBalance {
+            denom,
+            balance,
+            required: asset.amount,
+        });
+    }
+    attribute_outflow(
+        deps.storage,
+        &denom,
+        balance,
+        asset.amount,
+        Outflow::Withdrawal,
+    )?;
+    transfer_asset(deps.storage, env, recipient, asset)
+}
+
+/// Builds the transfer of `asset` to a Paloma `recipient`, checked against the allowlist and
+/// rate limits of the Paloma chain itself.
+fn transfer_asset(
+    storage: &mut dyn Storage,
+    env: &Env,
+    recipient: &Addr,
+    asset: Asset,
+) -> Result<CosmosMsg<PalomaMsg>, ContractError> {
+    assert_allowed_recipient(storage, env, &env.block.chain_id, recipient.as_str())?;
+    consume_rate_limit(
+        storage,
+        env,
+        &env.block.chain_id,
+        &asset.info.to_string(),
+        asset.amount,
+    )?;
+    Ok(match asset.info {
+        AssetInfo::NativeToken { denom } => CosmosMsg::Bank(BankMsg::Send {
+            to_address: recipient.to_string(),
+            amount: vec![Coin {
+                denom,
+                amount: asset.amount,
+            }],
+        }),
+        AssetInfo::Token { contract_addr } => CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: contract_addr.to_string(),
+            msg: to_json_binary(&ExternalExecuteMsg::Transfer {
+                recipient: recipient.to_string(),
+                amount: asset.amount,
+            })?,
+            funds: vec![],
+        }),
+    })
+}
+
+/// Rejects `recipient` when the allowlist of `chain_id` is enabled and the recipient is
+/// not listed, was added in the current block or is still within the allowlist delay.
+fn assert_allowed_recipient(
+    storage: &dyn Storage,
+    env: &Env,
+    chain_id: &str,
+    recipient: &str,
+) -> Result<(), ContractError> {
+    if !allowlist_enabled(storage, env, chain_id)? {
+        return Ok(());
+    }
+    match ALLOWED_RECIPIENTS.may_load(storage, (chain_id, &recipient.to_lowercase()))? {
+        Some(allowed)
+            if env.block.height > allowed.added_at_height
+                && env.block.time.seconds() >= allowed.active_from =>
+        {
+            Ok(())
+        }
+        _ => Err(ContractError::RecipientNotAllowed {
+            chain_id: chain_id.to_string(),
+            recipient: recipient.to_string(),
+        }),
+    }
+}
+
+/// Whether the allowlist of `chain_id` is enforced, counting a requested disable only once
+/// it takes effect.
+fn allowlist_enabled(storage: &dyn Storage, env: &Env, chain_id: &str) -> StdResult<bool> {
+    if !ALLOWLIST_ENABLED
+        .may_load(storage, chain_id)?
+        .unwrap_or_default()
+    {
+        return Ok(false);
+    }
+    Ok(ALLOWLIST_DISABLED_FROM
+        .may_load(storage, chain_id)?
+        .is_none_or(|disabled_from| env.block.time.seconds() < disabled_from))
+}
+
+/// Returns the allowlist delay in force, applying a lowered delay once it is due.
+fn current_allowlist_delay(state: &mut State, env: &Env) -> u64 {
+    if let Some(pending) = &state.pending_allowlist_delay {
+        if env.block.time.seconds() >= pending.effective_at {
+            state.allowlist_delay = pending.allowlist_delay;
+            state.pending_allowlist_delay = None;
+        }
+    }
+    state.allowlist_delay
+}
+
+/// Raises the allowlist delay at once. A lower delay only applies after the current delay
+/// has passed, so it cannot shorten the wait of a recipient added in the meantime.
+fn update_allowlist_delay(state: &mut State, env: &Env, allowlist_delay: u64) {
+    let current = current_allowlist_delay(state, env);
+    if allowlist_delay >= current {
+        state.allowlist_delay = allowlist_delay;
+        state.pending_allowlist_delay = None;
+    } else {
+        state.pending_allowlist_delay = Some(PendingAllowlistDelay {
+            allowlist_delay,
+            effective_at: env.block.time.seconds() + current,
+        });
+    }
+}
+
+/// Amount counted against a rate limit at `now`. Usage decays linearly, so the full
+/// `max_amount` is available again one window after the last outflow.
+fn rate_limit_used(rate_limit: &RateLimit, now: u64) -> Uint128 {
+    let elapsed = now
+        .saturating_sub(rate_limit.updated_at)
+        .min(rate_limit.window);
+    rate_limit.used.saturating_sub(
+        rate_limit
+            .max_amount
+            .multiply_ratio(elapsed, rate_limit.window),
+    )
+}
+
+/// Saves the rate limit of `denom` to `chain_id`, keeping the usage already counted. Unless
+/// `allow_loosening`, only a new limit or a tighter one is accepted.
+fn save_rate_limit(
+    storage: &mut dyn Storage,
+    chain_id: &str,
+    denom: &str,
+    max_amount: Uint128,
+    window: u64,
+    allow_loosening: bool,
+) -> Result<(), ContractError> {
+    if window == 0 {
+        return Err(ContractError::InvalidRateLimit {});
+    }
+    let (used, updated_at) = match RATE_LIMITS.may_load(storage, (chain_id, denom))? {
+        Some(current) => {
+            if !allow_loosening && (max_amount > current.max_amount || window < current.window) {
+                return Err(ContractError::RateLimitLoosened {});
+            }
+            (current.used, current.updated_at)
+        }
+        None => (Uint128::zero(), 0),
+    };
+    RATE_LIMITS.save(
+        storage,
+        (chain_id, denom),
+        &RateLimit {
+            max_amount,
+            window,
+            used,
+            updated_at,
+        },
+    )?;
+    Ok(())
+}
+
+/// Counts an outflow of `amount` of `denom` to `chain_id` against its rate limit, if any.
+fn consume_rate_limit(
+    storage: &mut dyn Storage,
+    env: &Env,
+    chain_id: &str,
+    denom: &str,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    let Some(mut rate_limit) = RATE_LIMITS.may_load(storage, (chain_id, denom))? else {
+        return Ok(());
+    };
+    let now = env.block.time.seconds();
+    let used = rate_limit_used(&rate_limit, now);
+    if used + amount > rate_limit.max_amount {
+        return Err(ContractError::RateLimitExceeded {
+            chain_id: chain_id.to_string(),
+            denom: denom.to_string(),
+            remaining: rate_limit.max_amount.saturating_sub(used),
+        });
+    }
+    rate_limit.used = used + amount;
+    rate_limit.updated_at = now;
+    RATE_LIMITS.save(storage, (chain_id, denom), &rate_limit)?;
+    Ok(())
+}
+
+/// Returns the offer coin of a route starting with a native token.
+fn route_offer(operations: &[SwapOperation], amount: Uint128) -> Result<Coin, ContractError> {
+    match operations.first().map(SwapOperation::offer_asset_info) {
+        Some(AssetInfo::NativeToken { denom }) => Ok(Coin {
+            denom: denom.clone(),
+            amount,
+        }),
+        Some(offer_asset_info) => Err(ContractError::FundsMismatch {
+            expected: offer_asset_info.to_string(),
+        }),
+        None => Err(ContractError::EmptyRoute {}),
+    }
+}
+
+/// Wraps `swap` in a submessage whose reply measures the amount of `ask_asset_info`
+/// the contract received.
+fn measured_swap(
+    deps: DepsMut,
+    env: &Env,
+    swap: CosmosMsg<PalomaMsg>,
+    ask_asset_info: AssetInfo,
+    purpose: SwapPurpose,
+) -> Result<SubMsg<PalomaMsg>, ContractError> {
+    let balance_before =
+        query_asset_balance(deps.as_ref(), &ask_asset_info, &env.contract.address)?;
+    PENDING_SWAP.save(
+        deps.storage,
+        &PendingSwap {
+            ask_asset_info,
+            balance_before,
+            purpose,
+        },
+    )?;
+    Ok(SubMsg::reply_on_success(swap, SWAP_REPLY_ID))
+}
+
+fn query_asset_balance(deps: Deps, asset_info: &AssetInfo, address: &Addr) -> StdResult<Uint128> {
+    match asset_info {
+        AssetInfo::NativeToken { denom } => Ok(deps.querier.query_balance(address, denom)?.amount),
+        AssetInfo::Token { contract_addr } => {
+            let response: BalanceResponse = deps.querier.query_wasm_smart(
+                contract_addr,
+                &ExternalQueryMsg::Balance {
+                    address: address.to_string(),
+                },
+            )?;
+            Ok(response.balance)
+        }
+    }
+}
+
+/// Compares the router's simulated return with the oracle price when a price guard is
+/// configured for the route's offer and ask assets. Returns the lowest return the guard
+/// accepts.
+fn check_price_guard(
+    deps: Deps,
+    env: &Env,
+    dex_router: &Addr,
+    operations: &[SwapOperation],
+    offer_amount: Uint128,
+) -> Result<Option<Uint128>, ContractError> {
+    let (Some(first), Some(last)) = (operations.first(), operations.last()) else {
+        return Ok(None);
+    };
+    let Some(guard) = PRICE_GUARDS.may_load(
+        deps.storage,
+        (
+            first.offer_asset_info().to_string(),
+            last.ask_asset_info().to_string(),
+        ),
+    )?
+    else {
+        return Ok(None);
+    };
+    let reference: ReferenceData = deps.querier.query_wasm_smart(
+        &guard.oracle,
+        &ExternalQueryMsg::GetReferenceData {
+            base_symbol: guard.base_symbol,
+            quote_symbol: guard.quote_symbol,
+        },
+    )?;
+    let updated_at = reference
+        .last_updated_base
+        .min(reference.last_updated_quote);
+    if env.block.time.seconds().saturating_sub(updated_at) > guard.max_staleness {
+        return Err(ContractError::StaleOraclePrice { updated_at });
+    }
+    // the oracle rate is scaled by 1e18 and quoted in whole units of each asset
+    let expected = Uint256::from(offer_amount)
+        .checked_mul(reference.rate)
+        .map_err(StdError::from)?
+        .multiply_ratio(
+            Uint256::from(10u8).pow(guard.ask_decimals.into()),
+            Uint256::from(10u8).pow(18 + u32::from(guard.offer_decimals)),
+        );
+    let max_deviation = Decimal256::from(guard.max_deviation);
+    let floor = expected.mul_floor(Decimal256::one() - max_deviation);
+    let ceiling = expected.mul_ceil(Decimal256::one() + max_deviation);
+    let simulated = simulate_swap(deps, dex_router, offer_amount, operations)?;
+    let simulated_amount = Uint256::from(simulated);
+    if simulated_amount < floor || simulated_amount > ceiling {
+        return Err(ContractError::PriceDeviation {
+            expected: expected.to_string(),
+            simulated,
+        });
+    }
+    Ok(Some(Uint128::try_from(floor).map_err(StdError::from)?))
+}
+
+fn simulate_swap(
+    deps: Deps,
+    dex_router: &Addr,
+    offer_amount: Uint128,
+    operations: &[SwapOperation],
+) -> StdResult<Uint128> {
+    let response: SimulateSwapOperationsResponse = deps.querier.query_wasm_smart(
+        dex_router,
+        &ExternalQueryMsg::SimulateSwapOperations {
+            offer_amount,
+            operations: operations.to_vec(),
+        },
+    )?;
+    Ok(response.amount)
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::GetState {} => to_json_binary(&STATE.load(deps.storage)?),
+        QueryMsg::GetPriceGuard {
+            offer_asset_info,
+            ask_asset_info,
+        } => to_json_binary(&PRICE_GUARDS.load(
+            deps.storage,
+            (offer_asset_info.to_string(), ask_asset_info.to_string()),
+        )?),
+        QueryMsg::GetBridgeableDenoms { chain_reference_id } => {
+            let denoms = BRIDGEABLE_DENOMS
+                .prefix(&chain_reference_id)
+                .keys(deps.storage, None, None, Order::Ascending)
+                .collect::<StdResult<Vec<String>>>()?;
+            to_json_binary(&denoms)
+        }
+        QueryMsg::GetPreApproval { id } => to_json_binary(&PRE_APPROVALS.load(deps.storage, id)?),
+        QueryMsg::GetPreApprovals { start_after, limit } => {
+            let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+            let approvals = PRE_APPROVALS
+                .range(
+                    deps.storage,
+                    start_after.map(Bound::exclusive),
+                    None,
+                    Order::Ascending,
+                )
+                .take(limit)
+                .map(|item| item.map(|(_, approval)| approval))
+                .collect::<StdResult<Vec<PreApproval>>>()?;
+            to_json_binary(&approvals)
+        }
+        QueryMsg::GetKeepers {} => {
+            let keepers = KEEPERS
+                .keys(deps.storage, None, None, Order::Ascending)
+                .collect::<StdResult<Vec<Addr>>>()?;
+            to_json_binary(&keepers)
+        }
+        QueryMsg::GetBountyCaps {} => {
+            let caps = [
+                ActionKind::Exchange,
+                ActionKind::SendToEvm,
+                ActionKind::WithdrawPusd,
+            ]
+            .into_iter()
+            .flat_map(|kind| {
+                BOUNTY_CAPS
+                    .prefix(kind.as_str())
+                    .range(deps.storage, None, None, Order::Ascending)
+                    .map(move |item| item.map(|(denom, cap)| BountyCapInfo { kind, denom, cap }))
+            })
+            .collect::<StdResult<Vec<BountyCapInfo>>>()?;
+            to_json_binary(&caps)
+        }
+        QueryMsg::GetRoute { route_id } => to_json_binary(&ROUTES.load(deps.storage, &route_id)?),
+        QueryMsg::GetRoutes { start_after, limit } => {
+            let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+            let routes = ROUTES
+                .range(
+                    deps.storage,
+                    start_after.as_deref().map(Bound::exclusive),
+                    None,
+                    Order::Ascending,
+                )
+                .take(limit)
+                .map(|item| item.map(|(_, route)| route))
+                .collect::<StdResult<Vec<PurchaseRoute>>>()?;
+            to_json_binary(&routes)
+        }
+        QueryMsg::GetSweepRoutes { target } => {
+            let routes = SWEEP_ROUTES
+                .prefix(target.to_string())
+                .range(deps.storage, None, None, Order::Ascending)
+                .map(|item| item.map(|(_, route)| route))
+                .collect::<StdResult<Vec<SweepRoute>>>()?;
+            to_json_binary(&routes)
+        }
+        QueryMsg::GetDistribution {} => {
+            to_json_binary(&DISTRIBUTION.may_load(deps.storage)?.unwrap_or_default())
+        }
+        QueryMsg::GetDepositor { addr } => {
+            let depositor = deps.api.addr_validate(&addr)?;
+            let denoms = DEPOSITOR_SHARES
+                .prefix(&depositor)
+                .keys(deps.storage, None, None, Order::Ascending)
+                .collect::<StdResult<Vec<String>>>()?;
+            let mut deposits = vec![];
+            for denom in denoms {
+                let pool = DEPOSIT_POOLS.load(deps.storage, &denom)?;
+                let share = load_depositor_share(deps.storage, &depositor, &denom, &pool)?;
+                let remaining = if pool.shares.is_zero() {
+                    Uint128::zero()
+                } else {
+                    share.shares.multiply_ratio(pool.remaining, pool.shares)
+                };
+                deposits.push(DepositInfo {
+                    denom,
+                    deposited: share.deposited,
+                    remaining,
+                    purchased: share.purchased,
+                    bridged: share.bridged,
+                    withdrawn: share.withdrawn,
+                });
+            }
+            to_json_binary(&deposits)
+        }
+        QueryMsg::GetNativeDenoms {} => {
+            let denoms = NATIVE_DENOMS
+                .keys(deps.storage, None, None, Order::Ascending)
+                .collect::<StdResult<Vec<String>>>()?;
+            to_json_binary(&denoms)
+        }
+        QueryMsg::GetCw20Tokens {} => {
+            let tokens = CW20_TOKENS
+                .keys(deps.storage, None, None, Order::Ascending)
+                .collect::<StdResult<Vec<Addr>>>()?;
+            to_json_binary(&tokens)
+        }
+        QueryMsg::GetHoldings { start_after, limit } => {
+            let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+            // native denoms come first, so a CW20 start skips all of them
+            let (denoms, tokens) = match &start_after {
+                None => (Some(None), None),
+                Some(AssetInfo::NativeToken { denom }) => {
+                    (Some(Some(Bound::exclusive(denom.as_str()))), None)
+                }
+                Some(AssetInfo::Token { contract_addr }) => {
+                    (None, Some(Bound::exclusive(contract_addr)))
+                }
+            };
+            let mut assets = vec![];
+            if let Some(min) = denoms {
+                for denom in NATIVE_DENOMS
+                    .keys(deps.storage, min, None, Order::Ascending)
+                    .take(limit)
+                {
+                    assets.push(AssetInfo::NativeToken { denom: denom? });
+                }
+            }
+            for contract_addr in CW20_TOKENS
+                .keys(deps.storage, tokens, None, Order::Ascending)
+                .take(limit - assets.len())
+            {
+                assets.push(AssetInfo::Token {
+                    contract_addr: contract_addr?,
+                });
+            }
+            let holdings = assets
+                .into_iter()
+                .map(|info| {
+                    let amount = query_asset_balance(deps, &info, &env.contract.address)?;
+                    Ok(Asset { info, amount })
+                })
+                .collect::<StdResult<Vec<Asset>>>()?;
+            to_json_binary(&holdings)
+        }
+        QueryMsg::GetHolding { asset } => {
+            let amount = query_asset_balance(deps, &asset, &env.contract.address)?;
+            to_json_binary(&Asset {
+                info: asset,
+                amount,
+            })
+        }
+        QueryMsg::GetOutstandingPusdWithdrawals {
+            pusd_manager,
+            start_after,
+            limit,
+        } => {
+            let pusd_manager = match pusd_manager {
+                Some(pusd_manager) => pusd_manager,
+                None => STATE
+                    .load(deps.storage)?
+                    .pusd_manager
+                    .ok_or_else(|| StdError::not_found("default pusd_manager"))?,
+            };
+            let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+            let withdrawals = PUSD_WITHDRAWALS
+                .prefix(&pusd_manager)
+                .range(
+                    deps.storage,
+                    start_after.map(Bound::exclusive),
+                    None,
+                    Order::Ascending,
+                )
+                .filter(|item| {
+                    item.as_ref().map_or(true, |(_, withdrawal)| {
+                        withdrawal.status == WithdrawalStatus::Pending
+                    })
+                })
+                .take(limit)
+                .map(|item| item.map(|(_, withdrawal)| withdrawal))
+                .collect::<StdResult<Vec<PusdWithdrawal>>>()?;
+            to_json_binary(&withdrawals)
+        }
+        QueryMsg::GetUnresolvedPusdWithdrawals { start_after, limit } => {
+            let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+            let withdrawals = UNRESOLVED_WITHDRAWALS
+                .range(
+                    deps.storage,
+                    start_after.map(Bound::exclusive),
+                    None,
+                    Order::Ascending,
+                )
+                .take(limit)
+                .map(|item| item.map(|(_, withdrawal)| withdrawal))
+                .collect::<StdResult<Vec<UnresolvedWithdrawal>>>()?;
+            to_json_binary(&withdrawals)
+        }
+        QueryMsg::GetPusdWithdrawal {
+            pusd_manager,
+            nonce,
+        } => {
+            let (pusd_manager, _) = resolve_pusd_manager(deps.storage, pusd_manager)
+                .map_err(|err| StdError::generic_err(err.to_string()))?;
+            let withdrawal: PusdManagerWithdrawal = deps
+                .querier
+                .query_wasm_smart(pusd_manager, &ExternalQueryMsg::GetWithdrawal { nonce })?;
+            to_json_binary(&withdrawal)
+        }
+        QueryMsg::GetPusdBalance { pusd_manager } => {
+            let (_, denom) = resolve_pusd_manager(deps.storage, pusd_manager)
+                .map_err(|err| StdError::generic_err(err.to_string()))?;
+            to_json_binary(&deps.querier.query_balance(env.contract.address, denom)?)
+        }
+        QueryMsg::GetPusdManagers {} => {
+            let default = STATE.load(deps.storage)?.pusd_manager;
+            let pusd_managers = PUSD_MANAGERS
+                .range(deps.storage, None, None, Order::Ascending)
+                .map(|item| {
+                    item.map(|(pusd_manager, denom)| PusdManagerInfo {
+                        is_default: default.as_ref() == Some(&pusd_manager),
+                        pusd_manager,
+                        denom,
+                    })
+                })
+                .collect::<StdResult<Vec<PusdManagerInfo>>>()?;
+            to_json_binary(&pusd_managers)
+        }
+        QueryMsg::GetAllowlist { chain_id } => {
+            let recipients = ALLOWED_RECIPIENTS
+                .prefix(&chain_id)
+                .range(deps.storage, None, None, Order::Ascending)
+                .map(|item| {
+                    item.map(|(recipient, allowed)| AllowedRecipientInfo {
+                        recipient,
+                        added_at_height: allowed.added_at_height,
+                        active_from: allowed.active_from,
+                    })
+                })
+                .collect::<StdResult<Vec<AllowedRecipientInfo>>>()?;
+            to_json_binary(&AllowlistResponse {
+                enabled: allowlist_enabled(deps.storage, &env, &chain_id)?,
+                disabled_from: ALLOWLIST_DISABLED_FROM.may_load(deps.storage, &chain_id)?,
+                recipients,
+            })
+        }
+        QueryMsg::GetRateLimit { chain_id, denom } => {
+            let rate_limit = RATE_LIMITS.load(deps.storage, (&chain_id, &denom))?;
+            let used = rate_limit_used(&rate_limit, env.block.time.seconds());
+            to_json_binary(&RateLimitResponse {
+                max_amount: rate_limit.max_amount,
+                window: rate_limit.window,
+                used,
+                remaining: rate_limit.max_amount.saturating_sub(used),
+            })
+        }
+        QueryMsg::GetOutgoingTransfer { transaction_id } => {
+            to_json_binary(&OUTGOING_TRANSFERS.load(deps.storage, transaction_id)?)
+        }
+        QueryMsg::GetOutgoingTransfers { start_after, limit } => {
+            let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+            let transfers = OUTGOING_TRANSFERS
+                .range(
+                    deps.storage,
+                    start_after.map(Bound::exclusive),
+                    None,
+                    Order::Ascending,
+                )
+                .take(limit)
+                .map(|item| item.map(|(_, transfer)| transfer))
+                .collect::<StdResult<Vec<OutgoingTransfer>>>()?;
+            to_json_binary(&transfers)
+        }
+        QueryMsg::GetUnresolvedTransfers { start_after, limit } => {
+            let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+            let transfers = UNRESOLVED_TRANSFERS
+                .range(
+                    deps.storage,
+                    start_after.map(Bound::exclusive),
+                    None,
+                    Order::Ascending,
+                )
+                .take(limit)
+                .map(|item| item.map(|(_, transfer)| transfer))
+                .collect::<StdResult<Vec<UnresolvedTransfer>>>()?;
+            to_json_binary(&transfers)
+        }
+        QueryMsg::GetSchedule { schedule_id } => {
+            to_json_binary(&SCHEDULES.load(deps.storage, schedule_id)?)
+        }
+        QueryMsg::GetSchedules { start_after, limit } => {
+            let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+            let schedules = SCHEDULES
+                .range(
+                    deps.storage,
+                    start_after.map(Bound::exclusive),
+                    None,
+                    Order::Ascending,
+                )
+                .take(limit)
+                .map(|item| item.map(|(_, schedule)| schedule))
+                .collect::<StdResult<Vec<Schedule>>>()?;
+            to_json_binary(&schedules)
+        }
+        QueryMsg::GetOrder { order_id } => to_json_binary(&ORDERS.load(deps.storage, order_id)?),
+        QueryMsg::GetOrders { start_after, limit } => {
+            let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+            let orders = ORDERS
+                .range(
+                    deps.storage,
+                    start_after.map(Bound::exclusive),
+                    None,
+                    Order::Ascending,
+                )
+                .take(limit)
+                .map(|item| item.map(|(_, order)| order))
+                .collect::<StdResult<Vec<PurchaseOrder>>>()?;
+            to_json_binary(&orders)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg::SplitLeg;
+    use crate::state::DistributionShare;
+    use cosmwasm_std::testing::{
+        message_info, mock_dependencies, mock_env, MockApi, MockQuerier, MockStorage,
+    };
+    use cosmwasm_std::{
+        coins, ContractResult, OwnedDeps, ReplyOn, SystemResult, Timestamp, WasmQuery,
+    };
+
+    type MockDeps = OwnedDeps<MockStorage, MockApi, MockQuerier>;
+
+    fn migrate_msg() -> MigrateMsg {
+        MigrateMsg {
+            allowlist_delay: None,
+            pusd_manager: None,
+            pusd_denom: None,
+        }
+    }
+
+    #[test]
+    fn migrates_deployed_layout() {
+        let mut deps = mock_dependencies();
+        let owner = deps.api.addr_make("owner");
+        // State as stored by 0.1.0
+        deps.storage
+            .set(b"state", format!(r#"{{"owners":["{owner}"]}}"#).as_bytes());
+        set_contract_version(&mut deps.storage, CONTRACT_NAME, "0.1.0").unwrap();
+        // the 0.1.0 layout lacks the fields added since
+        assert!(STATE.load(&deps.storage).is_err());
+
+        let pusd_manager = deps.api.addr_make("pusd_manager");
+        migrate(
+            deps.as_mut(),
+            mock_env(),
+            MigrateMsg {
+                allowlist_delay: Some(3600),
+                pusd_manager: Some(pusd_manager.to_string()),
+                pusd_denom: None,
+            },
+        )
+        .unwrap();
+
+        let state = STATE.load(&deps.storage).unwrap();
+        assert_eq!(state.owners, vec![owner]);
+        assert_eq!(state.allowlist_delay, 3600);
+        assert_eq!(state.pusd_manager, Some(pusd_manager.clone()));
+        assert!(!state.paused);
+        assert_eq!(state.pending_allowlist_delay, None);
+        assert_eq!(
+            PUSD_MANAGERS.load(&deps.storage, &pusd_manager).unwrap(),
+            format!("factory/{pusd_manager}/upusd")
+        );
+        let version = get_contract_version(&deps.storage).unwrap();
+        assert_eq!(version.version, CONTRACT_VERSION);
+    }
+
+    #[test]
+    fn refuses_downgrade() {
+        let mut deps = mock_dependencies();
+        set_contract_version(&mut deps.storage, CONTRACT_NAME, "99.0.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), migrate_msg()).unwrap_err();
+        assert!(matches!(err, ContractError::CannotDowngrade { .. }));
+    }
+
+    #[test]
+    fn refuses_other_contract() {
+        let mut deps = mock_dependencies();
+        set_contract_version(
+            &mut deps.storage,
+            "crates.io:token-purchaser-manager-cw",
+            "0.1.0",
+        )
+        .unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), migrate_msg()).unwrap_err();
+        assert!(matches!(err, ContractError::WrongContract { .. }));
+    }
+
+    fn env_at(seconds: u64) -> Env {
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(seconds);
+        env
+    }
+
+    fn instantiate_owner(deps: &mut MockDeps) -> MessageInfo {
+        let owner = deps.api.addr_make("owner");
+        let info = message_info(&owner, &[]);
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            InstantiateMsg {
+                owners: vec![owner.to_string()],
+            },
+        )
+        .unwrap();
+        info
+    }
+
+    fn native(denom: &str) -> AssetInfo {
+        AssetInfo::NativeToken {
+            denom: denom.to_string(),
+        }
+    }
+
+    fn hop(offer: &str, ask: &str) -> SwapOperation {
+        SwapOperation::AstroSwap {
+            offer_asset_info: native(offer),
+            ask_asset_info: native(ask),
+        }
+    }
+
+    /// Answers oracle queries with `rate` as of `updated_at`, and swap simulations through
+    /// `simulate`.
+    fn mock_market(
+        deps: &mut MockDeps,
+        rate: Uint256,
+        updated_at: u64,
+        simulate: impl Fn(Uint128) -> Uint128 + 'static,
+    ) {
+        deps.querier.update_wasm(move |query| {
+            let WasmQuery::Smart { msg, .. } = query else {
+                panic!("unexpected query {query:?}");
+            };
+            let response = match from_json(msg).unwrap() {
+                ExternalQueryMsg::GetReferenceData { .. } => to_json_binary(&ReferenceData {
+                    rate,
+                    last_updated_base: updated_at,
+                    last_updated_quote: updated_at,
+                }),
+                ExternalQueryMsg::SimulateSwapOperations { offer_amount, .. } => {
+                    to_json_binary(&SimulateSwapOperationsResponse {
+                        amount: simulate(offer_amount),
+                    })
+                }
+                msg => panic!("unexpected query {msg:?}"),
+            };
+            SystemResult::Ok(ContractResult::Ok(response.unwrap()))
+        });
+    }
+
+    /// Reads the `minimum_receive` of a swap sent to a DEX router.
+    fn swap_minimum_receive(msg: &CosmosMsg<PalomaMsg>) -> Option<Uint128> {
+        let CosmosMsg::Wasm(WasmMsg::Execute { msg, .. }) = msg else {
+            panic!("not a swap: {msg:?}");
+        };
+        match from_json(msg).unwrap() {
+            ExternalExecuteMsg::ExecuteSwapOperations {
+                minimum_receive, ..
+            } => minimum_receive,
+            msg => panic!("not a swap: {msg:?}"),
+        }
+    }
+
+    /// Guards `offer` to `ask` swaps with a 1% deviation and a 60 second staleness limit.
+    fn set_price_guard(
+        deps: &mut MockDeps,
+        info: &MessageInfo,
+        offer: &str,
+        ask: &str,
+        offer_decimals: u8,
+        ask_decimals: u8,
+    ) -> Result<Response<PalomaMsg>, ContractError> {
+        let oracle = deps.api.addr_make("oracle");
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            ExecuteMsg::SetPriceGuard {
+                offer_asset_info: native(offer),
+                ask_asset_info: native(ask),
+                oracle: oracle.to_string(),
+                base_symbol: "BASE".to_string(),
+                quote_symbol: "QUOTE".to_string(),
+                offer_decimals,
+                ask_decimals,
+                max_deviation: Decimal::percent(1),
+                max_staleness: 60,
+            },
+        )
+    }
+
+    #[test]
+    fn rate_limit_usage_decays_over_window() {
+        let rate_limit = RateLimit {
+            max_amount: Uint128::new(1000),
+            window: 100,
+            used: Uint128::new(800),
+            updated_at: 1000,
+        };
+        assert_eq!(rate_limit_used(&rate_limit, 1000), Uint128::new(800));
+        assert_eq!(rate_limit_used(&rate_limit, 1025), Uint128::new(550));
+        assert_eq!(rate_limit_used(&rate_limit, 1080), Uint128::zero());
+        assert_eq!(rate_limit_used(&rate_limit, 5000), Uint128::zero());
+        // a clock behind the last update does not decay anything
+        assert_eq!(rate_limit_used(&rate_limit, 900), Uint128::new(800));
+    }
+
+    #[test]
+    fn consume_rate_limit_counts_outflows() {
+        let mut deps = mock_dependencies();
+        // denoms without a limit are not counted
+        consume_rate_limit(
+            &mut deps.storage,
+            &env_at(1000),
+            "chain",
+            "denom",
+            Uint128::MAX,
+        )
+        .unwrap();
+
+        save_rate_limit(
+            &mut deps.storage,
+            "chain",
+            "denom",
+            Uint128::new(1000),
+            100,
+            false,
+        )
+        .unwrap();
+        consume_rate_limit(
+            &mut deps.storage,
+            &env_at(1000),
+            "chain",
+            "denom",
+            Uint128::new(600),
+        )
+        .unwrap();
+        let err = consume_rate_limit(
+            &mut deps.storage,
+            &env_at(1000),
+            "chain",
+            "denom",
+            Uint128::new(500),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::RateLimitExceeded { remaining, .. } if remaining == Uint128::new(400)
+        ));
+
+        // half a window later 500 of the 600 used has decayed
+        consume_rate_limit(
+            &mut deps.storage,
+            &env_at(1050),
+            "chain",
+            "denom",
+            Uint128::new(900),
+        )
+        .unwrap();
+        let rate_limit = RATE_LIMITS.load(&deps.storage, ("chain", "denom")).unwrap();
+        assert_eq!(rate_limit.used, Uint128::new(1000));
+        assert_eq!(rate_limit.updated_at, 1050);
+        let err = consume_rate_limit(
+            &mut deps.storage,
+            &env_at(1050),
+            "chain",
+            "denom",
+            Uint128::one(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::RateLimitExceeded { .. }));
+    }
+
+    #[test]
+    fn owners_can_only_tighten_rate_limits() {
+        let mut deps = mock_dependencies();
+        save_rate_limit(
+            &mut deps.storage,
+            "chain",
+            "denom",
+            Uint128::new(1000),
+            100,
+            false,
+        )
+        .unwrap();
+        consume_rate_limit(
+            &mut deps.storage,
+            &env_at(1000),
+            "chain",
+            "denom",
+            Uint128::new(300),
+        )
+        .unwrap();
+
+        save_rate_limit(
+            &mut deps.storage,
+            "chain",
+            "denom",
+            Uint128::new(500),
+            200,
+            false,
+        )
+        .unwrap();
+        let rate_limit = RATE_LIMITS.load(&deps.storage, ("chain", "denom")).unwrap();
+        assert_eq!(rate_limit.used, Uint128::new(300));
+
+        let err = save_rate_limit(
+            &mut deps.storage,
+            "chain",
+            "denom",
+            Uint128::new(501),
+            200,
+            false,
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::RateLimitLoosened {}));
+        let err = save_rate_limit(
+            &mut deps.storage,
+            "chain",
+            "denom",
+            Uint128::new(500),
+            199,
+            false,
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::RateLimitLoosened {}));
+
+        save_rate_limit(
+            &mut deps.storage,
+            "chain",
+            "denom",
+            Uint128::new(5000),
+            10,
+            true,
+        )
+        .unwrap();
+        let rate_limit = RATE_LIMITS.load(&deps.storage, ("chain", "denom")).unwrap();
+        assert_eq!(rate_limit.max_amount, Uint128::new(5000));
+    }
+
+    #[test]
+    fn allowlist_changes_wait_out_the_delay() {
+        let mut deps = mock_dependencies();
+        let owner = deps.api.addr_make("owner");
+        let info = message_info(&owner, &[]);
+        instantiate(
+            deps.as_mut(),
+            env_at(1000),
+            info.clone(),
+            InstantiateMsg {
+                owners: vec![owner.to_string()],
+            },
+        )
+        .unwrap();
+        let run = |deps: &mut OwnedDeps<_, _, _>, seconds: u64, msg: ExecuteMsg| {
+            execute(deps.as_mut(), env_at(seconds), info.clone(), msg).unwrap();
+        };
+        // raising the delay applies at once
+        run(
+            &mut deps,
+            1000,
+            ExecuteMsg::UpdateConfig {
+                allowlist_delay: Some(100),
+            },
+        );
+        run(
+            &mut deps,
+            1000,
+            ExecuteMsg::SetAllowlistEnabled {
+                chain_id: "chain".to_string(),
+                enabled: true,
+            },
+        );
+
+        run(
+            &mut deps,
+            1000,
+            ExecuteMsg::UpdateConfig {
+                allowlist_delay: Some(0),
+            },
+        );
+        run(
+            &mut deps,
+            1050,
+            ExecuteMsg::AddAllowedRecipient {
+                chain_id: "chain".to_string(),
+                recipient: "0xearly".to_string(),
+            },
+        );
+        run(
+            &mut deps,
+            1100,
+            ExecuteMsg::AddAllowedRecipient {
+                chain_id: "chain".to_string(),
+                recipient: "0xlate".to_string(),
+            },
+        );
+        let early = ALLOWED_RECIPIENTS
+            .load(&deps.storage, ("chain", "0xearly"))
+            .unwrap();
+        assert_eq!(early.active_from, 1150);
+        let late = ALLOWED_RECIPIENTS
+            .load(&deps.storage, ("chain", "0xlate"))
+            .unwrap();
+        assert_eq!(late.active_from, 1100);
+
+        // with the delay back at 100, disabling the allowlist waits 100 seconds
+        run(
+            &mut deps,
+            1100,
+            ExecuteMsg::UpdateConfig {
+                allowlist_delay: Some(100),
+            },
+        );
+        run(
+            &mut deps,
+            1200,
+            ExecuteMsg::SetAllowlistEnabled {
+                chain_id: "chain".to_string(),
+                enabled: false,
+            },
+        );
+        assert!(allowlist_enabled(&deps.storage, &env_at(1299), "chain").unwrap());
+        assert!(!allowlist_enabled(&deps.storage, &env_at(1300), "chain").unwrap());
+    }
+
+    fn evm_share(recipient: &str, weight_bps: u16) -> DistributionShare {
+        DistributionShare {
+            target: DistributionTarget::Evm {
+                recipient: recipient.to_string(),
+                chain_reference_id: "ethereum".to_string(),
+            },
+            weight_bps,
+        }
+    }
+
+    #[test]
+    fn distribution_gives_the_rounding_remainder_to_the_last_share() {
+        let mut deps = mock_dependencies();
+        let info = instantiate_owner(&mut deps);
+        let env = mock_env();
+        let treasury = deps.api.addr_make("treasury");
+        let ops = deps.api.addr_make("ops");
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            info.clone(),
+            ExecuteMsg::SetBridgeableDenom {
+                chain_reference_id: "ethereum".to_string(),
+                denom: "ugrain".to_string(),
+                bridgeable: true,
+            },
+        )
+        .unwrap();
+        let native_share = |address: &Addr| DistributionShare {
+            target: DistributionTarget::Native {
+                address: address.clone(),
+            },
+            weight_bps: 3333,
+        };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            info.clone(),
+            ExecuteMsg::SetDistribution {
+                shares: vec![native_share(&treasury), native_share(&ops)],
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidDistribution { .. }));
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            info.clone(),
+            ExecuteMsg::SetDistribution {
+                shares: vec![
+                    native_share(&treasury),
+                    native_share(&ops),
+                    evm_share("0xbuyback", 3334),
+                ],
+            },
+        )
+        .unwrap();
+
+        deps.querier
+            .bank
+            .update_balance(&env.contract.address, coins(1000, "ugrain"));
+        let res = execute(
+            deps.as_mut(),
+            env,
+            info,
+            ExecuteMsg::Distribute {
+                denom: "ugrain".to_string(),
+            },
+        )
+        .unwrap();
+        let bank_send = |to: &Addr, amount: u128| {
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: to.to_string(),
+                amount: coins(amount, "ugrain"),
+            })
+        };
+        assert_eq!(res.messages[0].msg, bank_send(&treasury, 333));
+        assert_eq!(res.messages[1].msg, bank_send(&ops, 333));
+        let CosmosMsg::Custom(PalomaMsg::SkywayMsg {
+            send_tx: Some(send_tx),
+            ..
+        }) = &res.messages[2].msg
+        else {
+            panic!("expected a Skyway send");
+        };
+        assert_eq!(send_tx.amount, "334ugrain");
+        assert_eq!(send_tx.remote_chain_destination_address, "0xbuyback");
+    }
+
+    #[test]
+    fn distribution_targets_are_allowlisted_and_rate_limited() {
+        let mut deps = mock_dependencies();
+        let info = instantiate_owner(&mut deps);
+        let env = mock_env();
+        let mut next_block = mock_env();
+        next_block.block.height += 1;
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            info.clone(),
+            ExecuteMsg::SetBridgeableDenom {
+                chain_reference_id: "ethereum".to_string(),
+                denom: "ugrain".to_string(),
+                bridgeable: true,
+            },
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            info.clone(),
+            ExecuteMsg::SetAllowlistEnabled {
+                chain_id: "ethereum".to_string(),
+                enabled: true,
+            },
+        )
+        .unwrap();
+        let set_distribution = |deps: &mut MockDeps, env: &Env| {
+            execute(
+                deps.as_mut(),
+                env.clone(),
+                info.clone(),
+                ExecuteMsg::SetDistribution {
+                    shares: vec![evm_share("0xBuyback", 10000)],
+                },
+            )
+        };
+
+        let err = set_distribution(&mut deps, &env).unwrap_err();
+        assert!(matches!(err, ContractError::RecipientNotAllowed { .. }));
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            info.clone(),
+            ExecuteMsg::AddAllowedRecipient {
+                chain_id: "ethereum".to_string(),
+                recipient: "0xbuyback".to_string(),
+            },
+        )
+        .unwrap();
+        // a recipient added in the current block is not allowed yet
+        let err = set_distribution(&mut deps, &env).unwrap_err();
+        assert!(matches!(err, ContractError::RecipientNotAllowed { .. }));
+        set_distribution(&mut deps, &next_block).unwrap();
+
+        execute(
+            deps.as_mut(),
+            next_block.clone(),
+            info.clone(),
+            ExecuteMsg::SetRateLimit {
+                chain_id: "ethereum".to_string(),
+                denom: "ugrain".to_string(),
+                max_amount: Uint128::new(999),
+                window: 3600,
+            },
+        )
+        .unwrap();
+        deps.querier
+            .bank
+            .update_balance(&next_block.contract.address, coins(1000, "ugrain"));
+        let distribute = ExecuteMsg::Distribute {
+            denom: "ugrain".to_string(),
+        };
+        let err = execute(
+            deps.as_mut(),
+            next_block.clone(),
+            info.clone(),
+            distribute.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::RateLimitExceeded { .. }));
+
+        // a recipient removed after SetDistribution is rejected at payout
+        execute(
+            deps.as_mut(),
+            next_block.clone(),
+            info.clone(),
+            ExecuteMsg::RemoveAllowedRecipient {
+                chain_id: "ethereum".to_string(),
+                recipient: "0xbuyback".to_string(),
+            },
+        )
+        .unwrap();
+        deps.querier
+            .bank
+            .update_balance(&next_block.contract.address, coins(900, "ugrain"));
+        let err = execute(deps.as_mut(), next_block, info, distribute).unwrap_err();
+        assert!(matches!(err, ContractError::RecipientNotAllowed { .. }));
+    }
+
+    fn reply_with_events(id: u64, events: Vec<Event>) -> Reply {
+        #[allow(deprecated)]
+        let response = cosmwasm_std::SubMsgResponse {
+            events,
+            data: None,
+            msg_responses: vec![],
+        };
+        Reply {
+            id,
+            payload: Binary::default(),
+            gas_used: 0,
+            result: cosmwasm_std::SubMsgResult::Ok(response),
+        }
+    }
+
+    #[test]
+    fn parses_skyway_transaction_ids() {
+        let plain = Event::new("send_to_evm").add_attribute("outgoing_tx_id", "7");
+        assert_eq!(parse_transaction_id(&[plain]), Some(7));
+        let typed = Event::new("palomachain.paloma.skyway.EventOutgoingTxId")
+            .add_attribute("message", "\"send_to_remote\"")
+            .add_attribute("tx_id", "\"12\"");
+        assert_eq!(parse_transaction_id(&[typed]), Some(12));
+        // a tx_id on any other event is not the Skyway id
+        let other = Event::new("wasm").add_attribute("tx_id", "3");
+        assert_eq!(parse_transaction_id(&[other]), None);
+    }
+
+    #[test]
+    fn transfers_sent_before_the_upgrade_can_be_registered() {
+        let mut deps = mock_dependencies();
+        let owner = deps.api.addr_make("owner");
+        let info = message_info(&owner, &[]);
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            InstantiateMsg {
+                owners: vec![owner.to_string()],
+            },
+        )
+        .unwrap();
+        let cancel = ExecuteMsg::CancelTx { transaction_id: 9 };
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), cancel.clone()).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::UnknownTransaction { transaction_id: 9 }
+        ));
+
+        let register = ExecuteMsg::RegisterTransfer {
+            transaction_id: 9,
+            chain_reference_id: "chain".to_string(),
+            recipient: "0xrecipient".to_string(),
+            amount: Coin::new(100u128, "ugrain"),
+        };
+        execute(deps.as_mut(), mock_env(), info.clone(), register.clone()).unwrap();
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), register).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::TransactionAlreadyRecorded { transaction_id: 9 }
+        ));
+        let response = execute(deps.as_mut(), mock_env(), info, cancel).unwrap();
+        assert_eq!(response.messages.len(), 1);
+        let transfer = OUTGOING_TRANSFERS.load(&deps.storage, 9).unwrap();
+        assert_eq!(transfer.status, TransferStatus::Cancelled);
+    }
+
+    #[test]
+    fn transfers_without_a_transaction_id_stay_unresolved() {
+        let mut deps = mock_dependencies();
+        let owner = deps.api.addr_make("owner");
+        let info = message_info(&owner, &[]);
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            InstantiateMsg {
+                owners: vec![owner.to_string()],
+            },
+        )
+        .unwrap();
+        let pending = PendingTransfer {
+            recipient: "0xrecipient".to_string(),
+            amount: Coin::new(100u128, "ugrain"),
+            chain_reference_id: "chain".to_string(),
+        };
+        PENDING_TRANSFERS
+            .push_back(&mut deps.storage, &pending)
+            .unwrap();
+
+        reply(
+            deps.as_mut(),
+            mock_env(),
+            reply_with_events(SEND_TX_REPLY_ID, vec![Event::new("send_to_evm")]),
+        )
+        .unwrap();
+        let unresolved = UNRESOLVED_TRANSFERS.load(&deps.storage, 1).unwrap();
+        assert_eq!(unresolved.amount, pending.amount);
+        assert!(PENDING_TRANSFERS.is_empty(&deps.storage).unwrap());
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            ExecuteMsg::ResolveTransfer {
+                id: 1,
+                transaction_id: 42,
+            },
+        )
+        .unwrap();
+        assert!(!UNRESOLVED_TRANSFERS.has(&deps.storage, 1));
+        let transfer = OUTGOING_TRANSFERS.load(&deps.storage, 42).unwrap();
+        assert_eq!(transfer.recipient, pending.recipient);
+        assert_eq!(transfer.status, TransferStatus::Pending);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::ResolveTransfer {
+                id: 1,
+                transaction_id: 43,
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::UnknownUnresolvedTransfer { id: 1 }
+        ));
+    }
+
+    #[test]
+    fn parses_withdrawal_nonces() {
+        let deps = mock_dependencies();
+        let pusd_manager = deps.api.addr_make("pusd_manager");
+        let other = deps.api.addr_make("other");
+        let event = |contract: &Addr, nonce: &str| {
+            Event::new("wasm")
+                .add_attribute("_contract_address", contract.as_str())
+                .add_attribute("nonce", nonce)
+        };
+        assert_eq!(
+            parse_withdrawal_nonce(&[event(&pusd_manager, "5")], &pusd_manager),
+            Some(5)
+        );
+        // nonces emitted by other contracts are ignored
+        assert_eq!(
+            parse_withdrawal_nonce(
+                &[event(&other, "3"), event(&pusd_manager, "8")],
+                &pusd_manager
+            ),
+            Some(8)
+        );
+        assert_eq!(
+            parse_withdrawal_nonce(&[event(&other, "3")], &pusd_manager),
+            None
+        );
+        assert_eq!(
+            parse_withdrawal_nonce(&[event(&pusd_manager, "abc")], &pusd_manager),
+            None
+        );
+    }
+
+    #[test]
+    fn withdrawals_can_be_resolved_registered_and_completed() {
+        let mut deps = mock_dependencies();
+        let owner = deps.api.addr_make("owner");
+        let pusd_manager = deps.api.addr_make("pusd_manager");
+        let info = message_info(&owner, &[]);
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            InstantiateMsg {
+                owners: vec![owner.to_string()],
+            },
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            ExecuteMsg::SetPusdManager {
+                pusd_manager: pusd_manager.to_string(),
+                denom: None,
+                is_default: true,
+            },
+        )
+        .unwrap();
+        let outstanding = |deps: &OwnedDeps<_, _, _>| -> Vec<u64> {
+            let withdrawals: Vec<PusdWithdrawal> = from_json(
+                query(
+                    deps.as_ref(),
+                    mock_env(),
+                    QueryMsg::GetOutstandingPusdWithdrawals {
+                        pusd_manager: None,
+                        start_after: None,
+                        limit: None,
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap();
+            withdrawals
+                .iter()
+                .map(|withdrawal| withdrawal.nonce)
+                .collect()
+        };
+
+        // a reply without a nonce keeps the withdrawal as unresolved
+        PENDING_WITHDRAWALS
+            .push_back(
+                &mut deps.storage,
+                &PendingWithdrawal {
+                    pusd_manager: pusd_manager.clone(),
+                    chain_id: "chain".to_string(),
+                    recipient: "0xrecipient".to_string(),
+                    amount: Uint128::new(100),
+                },
+            )
+            .unwrap();
+        reply(
+            deps.as_mut(),
+            mock_env(),
+            reply_with_events(PUSD_WITHDRAW_REPLY_ID, vec![]),
+        )
+        .unwrap();
+        assert!(UNRESOLVED_WITHDRAWALS.has(&deps.storage, 1));
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            ExecuteMsg::ResolveWithdrawPusd { id: 1, nonce: 4 },
+        )
+        .unwrap();
+        assert!(!UNRESOLVED_WITHDRAWALS.has(&deps.storage, 1));
+
+        // a withdrawal made before the upgrade
+        let register = ExecuteMsg::RegisterWithdrawPusd {
+            pusd_manager: None,
+            nonce: 2,
+            chain_id: "chain".to_string(),
+            recipient: "0xrecipient".to_string(),
+            amount: Uint128::new(50),
+        };
+        execute(deps.as_mut(), mock_env(), info.clone(), register.clone()).unwrap();
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), register).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::WithdrawalAlreadyRecorded { nonce: 2 }
+        ));
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            ExecuteMsg::ReWithdrawPusd {
+                pusd_manager: None,
+                nonce: 2,
+            },
+        )
+        .unwrap();
+        assert_eq!(outstanding(&deps), vec![2, 4]);
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            ExecuteMsg::CompleteWithdrawPusd {
+                pusd_manager: None,
+                nonce: 4,
+            },
+        )
+        .unwrap();
+        assert_eq!(outstanding(&deps), vec![2]);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::CancelWithdrawPusd {
+                pusd_manager: None,
+                nonce: 4,
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::WithdrawalNotPending { nonce: 4 }
+        ));
+    }
+
+    fn depositor_share(storage: &dyn Storage, depositor: &Addr) -> DepositorShare {
+        let pool = DEPOSIT_POOLS.load(storage, "ugrain").unwrap();
+        load_depositor_share(storage, depositor, "ugrain", &pool).unwrap()
+    }
+
+    #[test]
+    fn deposits_mint_shares_at_the_pool_ratio() {
+        let mut deps = mock_dependencies();
+        let alice = deps.api.addr_make("alice");
+        let bob = deps.api.addr_make("bob");
+        let carol = deps.api.addr_make("carol");
+        record_deposit(&mut deps.storage, &alice, "ugrain", Uint128::new(100)).unwrap();
+        record_deposit(&mut deps.storage, &bob, "ugrain", Uint128::new(300)).unwrap();
+        assert_eq!(
+            depositor_share(&deps.storage, &alice).shares,
+            Uint128::new(100)
+        );
+        assert_eq!(
+            depositor_share(&deps.storage, &bob).shares,
+            Uint128::new(300)
+        );
+
+        // half of the deposits are spent, so a new deposit buys twice the shares
+        attribute_outflow(
+            &mut deps.storage,
+            "ugrain",
+            Uint128::new(400),
+            Uint128::new(200),
+            Outflow::Purchase,
+        )
+        .unwrap();
+        record_deposit(&mut deps.storage, &carol, "ugrain", Uint128::new(100)).unwrap();
+        let pool = DEPOSIT_POOLS.load(&deps.storage, "ugrain").unwrap();
+        assert_eq!(pool.shares, Uint128::new(600));
+        assert_eq!(pool.remaining, Uint128::new(300));
+        let carol_share = depositor_share(&deps.storage, &carol);
+        assert_eq!(carol_share.shares, Uint128::new(200));
+        assert_eq!(carol_share.purchased, Uint128::zero());
+    }
+
+    #[test]
+    fn outflows_are_attributed_pro_rata() {
+        let mut deps = mock_dependencies();
+        let alice = deps.api.addr_make("alice");
+        let bob = deps.api.addr_make("bob");
+        record_deposit(&mut deps.storage, &alice, "ugrain", Uint128::new(100)).unwrap();
+        record_deposit(&mut deps.storage, &bob, "ugrain", Uint128::new(300)).unwrap();
+
+        // deposits are a tenth of the balance, so a tenth of the outflow is theirs
+        attribute_outflow(
+            &mut deps.storage,
+            "ugrain",
+            Uint128::new(4000),
+            Uint128::new(800),
+            Outflow::Bridge,
+        )
+        .unwrap();
+        attribute_outflow(
+            &mut deps.storage,
+            "ugrain",
+            Uint128::new(320),
+            Uint128::new(160),
+            Outflow::Withdrawal,
+        )
+        .unwrap();
+        let alice_share = depositor_share(&deps.storage, &alice);
+        assert_eq!(alice_share.bridged, Uint128::new(20));
+        assert_eq!(alice_share.withdrawn, Uint128::new(40));
+        let bob_share = depositor_share(&deps.storage, &bob);
+        assert_eq!(bob_share.bridged, Uint128::new(60));
+        assert_eq!(bob_share.withdrawn, Uint128::new(120));
+        assert_eq!(bob_share.purchased, Uint128::zero());
+        let pool = DEPOSIT_POOLS.load(&deps.storage, "ugrain").unwrap();
+        assert_eq!(pool.remaining, Uint128::new(160));
+
+        // untracked denoms are ignored
+        attribute_outflow(
+            &mut deps.storage,
+            "uother",
+            Uint128::new(100),
+            Uint128::new(100),
+            Outflow::Purchase,
+        )
+        .unwrap();
+        assert!(!DEPOSIT_POOLS.has(&deps.storage, "uother"));
+    }
+
+    #[test]
+    fn exhausted_pools_are_settled_before_new_deposits() {
+        let mut deps = mock_dependencies();
+        let alice = deps.api.addr_make("alice");
+        let bob = deps.api.addr_make("bob");
+        record_deposit(&mut deps.storage, &alice, "ugrain", Uint128::new(100)).unwrap();
+        attribute_outflow(
+            &mut deps.storage,
+            "ugrain",
+            Uint128::new(100),
+            Uint128::new(100),
+            Outflow::Withdrawal,
+        )
+        .unwrap();
+        let pool = DEPOSIT_POOLS.load(&deps.storage, "ugrain").unwrap();
+        assert_eq!(pool.generation, 1);
+        assert_eq!(pool.shares, Uint128::zero());
+        let spent = SPENT_DEPOSIT_POOLS
+            .load(&deps.storage, ("ugrain", 0))
+            .unwrap();
+        assert_eq!(spent.remaining, Uint128::zero());
+
+        // a deposit into the new pool starts over at one share per token
+        record_deposit(&mut deps.storage, &bob, "ugrain", Uint128::new(50)).unwrap();
+        assert_eq!(
+            depositor_share(&deps.storage, &bob).shares,
+            Uint128::new(50)
+        );
+        let alice_share = depositor_share(&deps.storage, &alice);
+        assert_eq!(alice_share.generation, 1);
+        assert_eq!(alice_share.shares, Uint128::zero());
+        assert_eq!(alice_share.withdrawn, Uint128::new(100));
+
+        // the old shares take no part in the new pool's outflows
+        record_deposit(&mut deps.storage, &alice, "ugrain", Uint128::new(10)).unwrap();
+        attribute_outflow(
+            &mut deps.storage,
+            "ugrain",
+            Uint128::new(60),
+            Uint128::new(30),
+            Outflow::Purchase,
+        )
+        .unwrap();
+        let alice_share = depositor_share(&deps.storage, &alice);
+        assert_eq!(alice_share.shares, Uint128::new(10));
+        assert_eq!(alice_share.deposited, Uint128::new(110));
+        assert_eq!(alice_share.purchased, Uint128::new(5));
+        assert_eq!(alice_share.withdrawn, Uint128::new(100));
+        assert_eq!(
+            depositor_share(&deps.storage, &bob).purchased,
+            Uint128::new(25)
+        );
+    }
+
+    #[test]
+    fn price_guard_bounds_the_simulated_return() {
+        let mut deps = mock_dependencies();
+        let info = instantiate_owner(&mut deps);
+        let router = deps.api.addr_make("router");
+        let route = [hop("ugrain", "aeth")];
+        let offer = Uint128::new(1_000_000);
+        // routes without a guard are not checked
+        let guarded = check_price_guard(deps.as_ref(), &env_at(10_000), &router, &route, offer);
+        assert_eq!(guarded.unwrap(), None);
+
+        // 1 GRAIN (6 decimals) at 2.5 ETH (18 decimals) each
+        set_price_guard(&mut deps, &info, "ugrain", "aeth", 6, 18).unwrap();
+        let rate = Uint256::from(2_500_000_000_000_000_000u128);
+        let floor = 2_475_000_000_000_000_000u128;
+        let ceiling = 2_525_000_000_000_000_000u128;
+        for (simulated, accepted) in [
+            (floor, true),
+            (floor - 1, false),
+            (ceiling, true),
+            (ceiling + 1, false),
+        ] {
+            mock_market(&mut deps, rate, 10_000, move |_| Uint128::new(simulated));
+            let guarded = check_price_guard(deps.as_ref(), &env_at(10_000), &router, &route, offer);
+            if accepted {
+                // the floor becomes the swap's minimum receive
+                assert_eq!(guarded.unwrap(), Some(Uint128::new(floor)));
+            } else {
+                assert!(matches!(
+                    guarded.unwrap_err(),
+                    ContractError::PriceDeviation { .. }
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn price_guard_scales_eighteen_to_six_decimals() {
+        let mut deps = mock_dependencies();
+        let info = instantiate_owner(&mut deps);
+        let router = deps.api.addr_make("router");
+        let route = [hop("aeth", "ugrain")];
+        set_price_guard(&mut deps, &info, "aeth", "ugrain", 18, 6).unwrap();
+        // 2 ETH at 0.4 GRAIN each is expected to return 0.8 GRAIN
+        mock_market(
+            &mut deps,
+            Uint256::from(400_000_000_000_000_000u128),
+            10_000,
+            |_| Uint128::new(792_000),
+        );
+        let offer = Uint128::new(2_000_000_000_000_000_000);
+        let guarded = check_price_guard(deps.as_ref(), &env_at(10_000), &router, &route, offer);
+        assert_eq!(guarded.unwrap(), Some(Uint128::new(792_000)));
+
+        mock_market(
+            &mut deps,
+            Uint256::from(400_000_000_000_000_000u128),
+            10_000,
+            |_| Uint128::new(791_999),
+        );
+        let guarded = check_price_guard(deps.as_ref(), &env_at(10_000), &router, &route, offer);
+        assert!(matches!(
+            guarded.unwrap_err(),
+            ContractError::PriceDeviation { .. }
+        ));
+    }
+
+    #[test]
+    fn price_guard_rejects_stale_quotes() {
+        let mut deps = mock_dependencies();
+        let info = instantiate_owner(&mut deps);
+        let router = deps.api.addr_make("router");
+        let route = [hop("ugrain", "aeth")];
+        let offer = Uint128::new(1_000_000);
+        set_price_guard(&mut deps, &info, "ugrain", "aeth", 6, 18).unwrap();
+        let rate = Uint256::from(2_500_000_000_000_000_000u128);
+        let simulate = |_| Uint128::new(2_500_000_000_000_000_000);
+
+        mock_market(&mut deps, rate, 9_940, simulate);
+        let guarded = check_price_guard(deps.as_ref(), &env_at(10_000), &router, &route, offer);
+        assert!(guarded.is_ok());
+        mock_market(&mut deps, rate, 9_939, simulate);
+        let guarded = check_price_guard(deps.as_ref(), &env_at(10_000), &router, &route, offer);
+        assert!(matches!(
+            guarded.unwrap_err(),
+            ContractError::StaleOraclePrice { updated_at: 9_939 }
+        ));
+    }
+
+    #[test]
+    fn price_guard_decimals_are_capped() {
+        let mut deps = mock_dependencies();
+        let info = instantiate_owner(&mut deps);
+        set_price_guard(&mut deps, &info, "ugrain", "aeth", 18, 18).unwrap();
+        for (offer_decimals, ask_decimals) in [(19, 6), (6, 19)] {
+            let err = set_price_guard(
+                &mut deps,
+                &info,
+                "ugrain",
+                "aeth",
+                offer_decimals,
+                ask_decimals,
+            )
+            .unwrap_err();
+            assert!(matches!(
+                err,
+                ContractError::InvalidDecimals {
+                    decimals: 19,
+                    max: 18
+                }
+            ));
+        }
+    }
+
+    fn create_order(deps: &mut MockDeps, info: &MessageInfo, expiry: u64) {
+        let router = deps.api.addr_make("router");
+        execute(
+            deps.as_mut(),
+            env_at(10_000),
+            info.clone(),
+            ExecuteMsg::CreateOrder {
+                dex_router: router,
+                operations: vec![hop("ugrain", "upusd")],
+                offer: Coin::new(1000u128, "ugrain"),
+                target_price: Decimal::from_ratio(2u128, 1u128),
+                expiry,
+                max_spread: None,
+            },
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn orders_execute_once_at_or_below_their_target_price() {
+        let mut deps = mock_dependencies();
+        let info = instantiate_owner(&mut deps);
+        let keeper = message_info(&deps.api.addr_make("keeper"), &[]);
+        create_order(&mut deps, &info, 20_000);
+        let execute_order = ExecuteMsg::ExecuteOrder { order_id: 1 };
+
+        // 1000 ugrain for 499 upusd is above the target of 2 ugrain per upusd
+        mock_market(&mut deps, Uint256::zero(), 0, |_| Uint128::new(499));
+        let err = execute(
+            deps.as_mut(),
+            env_at(15_000),
+            keeper.clone(),
+            execute_order.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::PriceAboveTarget { .. }));
+
+        mock_market(&mut deps, Uint256::zero(), 0, |_| Uint128::new(500));
+        let response = execute(
+            deps.as_mut(),
+            env_at(15_000),
+            keeper.clone(),
+            execute_order.clone(),
+        )
+        .unwrap();
+        // the swap must return at least the target price
+        assert_eq!(
+            swap_minimum_receive(&response.messages[0].msg),
+            Some(Uint128::new(500))
+        );
+        assert!(!ORDERS.has(&deps.storage, 1));
+        execute(deps.as_mut(), env_at(15_000), keeper, execute_order).unwrap_err();
+    }
+
+    #[test]
+    fn orders_expire() {
+        let mut deps = mock_dependencies();
+        let info = instantiate_owner(&mut deps);
+        create_order(&mut deps, &info, 20_000);
+        mock_market(&mut deps, Uint256::zero(), 0, |_| Uint128::new(1000));
+        let err = execute(
+            deps.as_mut(),
+            env_at(20_001),
+            info.clone(),
+            ExecuteMsg::ExecuteOrder { order_id: 1 },
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::OrderExpired { expiry: 20_000 }
+        ));
+        execute(
+            deps.as_mut(),
+            env_at(20_000),
+            info,
+            ExecuteMsg::ExecuteOrder { order_id: 1 },
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn pruning_scans_one_page_of_orders() {
+        let mut deps = mock_dependencies();
+        let info = instantiate_owner(&mut deps);
+        for expiry in [15_000, 30_000, 15_000] {
+            create_order(&mut deps, &info, expiry);
+        }
+        let prune = |start_after| ExecuteMsg::PruneExpiredOrders {
+            start_after,
+            limit: Some(2),
+        };
+        let response = execute(deps.as_mut(), env_at(20_000), info.clone(), prune(None)).unwrap();
+        let attribute = |key: &str| {
+            response
+                .attributes
+                .iter()
+                .find(|attribute| attribute.key == key)
+                .map(|attribute| attribute.value.clone())
+        };
+        assert_eq!(attribute("count"), Some("1".to_string()));
+        assert_eq!(attribute("last_order_id"), Some("2".to_string()));
+        assert!(!ORDERS.has(&deps.storage, 1));
+        assert!(ORDERS.has(&deps.storage, 3));
+
+        execute(deps.as_mut(), env_at(20_000), info, prune(Some(2))).unwrap();
+        assert!(!ORDERS.has(&deps.storage, 3));
+        assert!(ORDERS.has(&deps.storage, 2));
+    }
+
+    fn pre_approve_exchange(
+        deps: &mut MockDeps,
+        info: &MessageInfo,
+        bounty: Option<Coin>,
+        permissionless: bool,
+    ) -> Result<Response<PalomaMsg>, ContractError> {
+        let router = deps.api.addr_make("router");
+        execute(
+            deps.as_mut(),
+            env_at(10_000),
+            info.clone(),
+            ExecuteMsg::PreApprove {
+                action: PreApprovedAction::Exchange {
+                    dex_router: router,
+                    operations: vec![hop("ugrain", "upusd")],
+                    minimum_receive: None,
+                    to: None,
+                    max_spread: None,
+                    funds: vec![Coin::new(1000u128, "ugrain")],
+                    cw20_amount: None,
+                },
+                not_before: 12_000,
+                bounty,
+                permissionless,
+            },
+        )
+    }
+
+    fn set_bounty_cap(deps: &mut MockDeps, info: &MessageInfo, cap: Option<u128>) {
+        execute(
+            deps.as_mut(),
+            env_at(10_000),
+            info.clone(),
+            ExecuteMsg::SetBountyCap {
+                kind: ActionKind::Exchange,
+                denom: "ugrain".to_string(),
+                cap: cap.map(Uint128::new),
+            },
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn pre_approved_bounties_are_capped() {
+        let mut deps = mock_dependencies();
+        let info = instantiate_owner(&mut deps);
+        let keeper = message_info(&deps.api.addr_make("keeper"), &[]);
+        // without a cap no bounty can be approved
+        let err = pre_approve_exchange(&mut deps, &info, Some(Coin::new(1u128, "ugrain")), true)
+            .unwrap_err();
+        assert!(matches!(err, ContractError::BountyExceedsCap { .. }));
+
+        set_bounty_cap(&mut deps, &info, Some(100));
+        let err = pre_approve_exchange(&mut deps, &info, Some(Coin::new(101u128, "ugrain")), true)
+            .unwrap_err();
+        assert!(matches!(err, ContractError::BountyExceedsCap { .. }));
+        pre_approve_exchange(&mut deps, &info, Some(Coin::new(100u128, "ugrain")), true).unwrap();
+        pre_approve_exchange(&mut deps, &info, Some(Coin::new(100u128, "ugrain")), true).unwrap();
+
+        // a cap lowered after the approval still applies
+        set_bounty_cap(&mut deps, &info, Some(40));
+        let response = execute(
+            deps.as_mut(),
+            env_at(12_000),
+            keeper.clone(),
+            ExecuteMsg::ExecutePreApproved { id: 1 },
+        )
+        .unwrap();
+        assert_eq!(response.messages.len(), 2);
+        assert_eq!(
+            response.messages[1].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: keeper.sender.to_string(),
+                amount: vec![Coin::new(40u128, "ugrain")],
+            })
+        );
+
+        // and a removed cap pays no bounty at all
+        set_bounty_cap(&mut deps, &info, None);
+        let response = execute(
+            deps.as_mut(),
+            env_at(12_000),
+            keeper,
+            ExecuteMsg::ExecutePreApproved { id: 2 },
+        )
+        .unwrap();
+        assert_eq!(response.messages.len(), 1);
+    }
+
+    #[test]
+    fn pre_approvals_run_once_from_not_before() {
+        let mut deps = mock_dependencies();
+        let info = instantiate_owner(&mut deps);
+        let keeper = message_info(&deps.api.addr_make("keeper"), &[]);
+        pre_approve_exchange(&mut deps, &info, None, true).unwrap();
+        let execute_approval = ExecuteMsg::ExecutePreApproved { id: 1 };
+
+        let err = execute(
+            deps.as_mut(),
+            env_at(11_999),
+            keeper.clone(),
+            execute_approval.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::PreApprovalNotReady { not_before: 12_000 }
+        ));
+        execute(
+            deps.as_mut(),
+            env_at(12_000),
+            keeper.clone(),
+            execute_approval.clone(),
+        )
+        .unwrap();
+        let err = execute(deps.as_mut(), env_at(12_000), keeper, execute_approval).unwrap_err();
+        assert!(matches!(err, ContractError::UnknownPreApproval { id: 1 }));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unauthorized")]
+    fn restricted_pre_approvals_need_a_keeper() {
+        let mut deps = mock_dependencies();
+        let info = instantiate_owner(&mut deps);
+        let stranger = message_info(&deps.api.addr_make("stranger"), &[]);
+        pre_approve_exchange(&mut deps, &info, None, false).unwrap();
+        let _ = execute(
+            deps.as_mut(),
+            env_at(12_000),
+            stranger,
+            ExecuteMsg::ExecutePreApproved { id: 1 },
+        );
+    }
+
+    #[test]
+    fn split_purchases_enforce_the_total_minimum_across_legs() {
+        let mut deps = mock_dependencies();
+        let info = instantiate_owner(&mut deps);
+        let router = deps.api.addr_make("router");
+        let contract = mock_env().contract.address;
+        deps.querier
+            .bank
+            .update_balance(&contract, vec![Coin::new(50u128, "upusd")]);
+        let response = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::ExchangeSplit {
+                legs: vec![
+                    SplitLeg {
+                        dex_router: router.clone(),
+                        operations: vec![hop("ugrain", "upusd")],
+                        offer_amount: Uint128::new(600),
+                    },
+                    SplitLeg {
+                        dex_router: router,
+                        operations: vec![hop("uatom", "upusd")],
+                        offer_amount: Uint128::new(400),
+                    },
+                ],
+                total_minimum_receive: Uint128::new(1000),
+                max_spread: None,
+            },
+        )
+        .unwrap();
+        // every leg runs in the same transaction, and only the last one replies, so a
+        // failing reply reverts them all
+        assert_eq!(response.messages.len(), 2);
+        assert_eq!(response.messages[0].reply_on, ReplyOn::Never);
+        assert_eq!(response.messages[1].reply_on, ReplyOn::Success);
+        assert_eq!(response.messages[1].id, SWAP_REPLY_ID);
+
+        // both legs together returned 999 of the 1000 required
+        let pending = PENDING_SWAP.load(&deps.storage).unwrap();
+        deps.querier
+            .bank
+            .update_balance(&contract, vec![Coin::new(1049u128, "upusd")]);
+        let err = reply(
+            deps.as_mut(),
+            mock_env(),
+            reply_with_events(SWAP_REPLY_ID, vec![]),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::SplitMinimumNotMet { received, minimum }
+                if received == Uint128::new(999) && minimum == Uint128::new(1000)
+        ));
+
+        // the chain reverts the failed transaction, restoring the pending swap
+        PENDING_SWAP.save(&mut deps.storage, &pending).unwrap();
+        deps.querier
+            .bank
+            .update_balance(&contract, vec![Coin::new(1050u128, "upusd")]);
+        reply(
+            deps.as_mut(),
+            mock_env(),
+            reply_with_events(SWAP_REPLY_ID, vec![]),
+        )
+        .unwrap();
+        assert!(PENDING_SWAP.may_load(&deps.storage).unwrap().is_none());
+    }
+
+    #[test]
+    fn purchase_and_bridge_forwards_the_amount_received() {
+        let mut deps = mock_dependencies();
+        let info = instantiate_owner(&mut deps);
+        let router = deps.api.addr_make("router");
+        let contract = mock_env().contract.address;
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            ExecuteMsg::SetBridgeableDenom {
+                chain_reference_id: "chain".to_string(),
+                denom: "upusd".to_string(),
+                bridgeable: true,
+            },
+        )
+        .unwrap();
+        deps.querier
+            .bank
+            .update_balance(&contract, vec![Coin::new(50u128, "upusd")]);
+        let response = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::PurchaseAndBridge {
+                dex_router: router,
+                operations: vec![hop("ugrain", "upusd")],
+                minimum_receive: None,
+                max_spread: None,
+                funds: vec![Coin::new(1000u128, "ugrain")],
+                recipient: "0xrecipient".to_string(),
+                chain_reference_id: "chain".to_string(),
+            },
+        )
+        .unwrap();
+        assert_eq!(response.messages[0].id, SWAP_REPLY_ID);
+        let pending = PENDING_SWAP.load(&deps.storage).unwrap();
+        assert_eq!(pending.balance_before, Uint128::new(50));
+
+        // the swap returned 480, on top of the 50 held before
+        deps.querier
+            .bank
+            .update_balance(&contract, vec![Coin::new(530u128, "upusd")]);
+        let response = reply(
+            deps.as_mut(),
+            mock_env(),
+            reply_with_events(SWAP_REPLY_ID, vec![]),
+        )
+        .unwrap();
+        assert!(PENDING_SWAP.may_load(&deps.storage).unwrap().is_none());
+        assert_eq!(response.messages.len(), 1);
+        assert_eq!(response.messages[0].id, SEND_TX_REPLY_ID);
+        assert_eq!(
+            response.messages[0].msg,
+            CosmosMsg::Custom(PalomaMsg::SkywayMsg {
+                send_tx: Some(SendTx {
+                    remote_chain_destination_address: "0xrecipient".to_string(),
+                    amount: "480upusd".to_string(),
+                    chain_reference_id: "chain".to_string(),
+                }),
+                cancel_tx: None,
+            })
+        );
+        let transfer = PENDING_TRANSFERS.front(&deps.storage).unwrap().unwrap();
+        assert_eq!(transfer.amount, Coin::new(480u128, "upusd"));
+    }
+
+    #[test]
+    fn purchase_pusd_and_withdraw_forwards_the_amount_received() {
+        let mut deps = mock_dependencies();
+        let info = instantiate_owner(&mut deps);
+        let router = deps.api.addr_make("router");
+        let pusd_manager = deps.api.addr_make("pusd_manager");
+        let pusd_denom = format!("factory/{pusd_manager}/upusd");
+        let contract = mock_env().contract.address;
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            ExecuteMsg::SetPusdManager {
+                pusd_manager: pusd_manager.to_string(),
+                denom: None,
+                is_default: true,
+            },
+        )
+        .unwrap();
+        deps.querier
+            .bank
+            .update_balance(&contract, vec![Coin::new(50u128, &pusd_denom)]);
+        let response = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::PurchasePusdAndWithdraw {
+                dex_router: router,
+                operations: vec![hop("ugrain", &pusd_denom)],
+                minimum_receive: None,
+                max_spread: None,
+                funds: vec![Coin::new(1000u128, "ugrain")],
+                pusd_manager: None,
+                chain_id: "chain".to_string(),
+                recipient: "0xrecipient".to_string(),
+            },
+        )
+        .unwrap();
+        assert_eq!(response.messages[0].id, SWAP_REPLY_ID);
+
+        deps.querier
+            .bank
+            .update_balance(&contract, vec![Coin::new(530u128, &pusd_denom)]);
+        let response = reply(
+            deps.as_mut(),
+            mock_env(),
+            reply_with_events(SWAP_REPLY_ID, vec![]),
+        )
+        .unwrap();
+        assert!(PENDING_SWAP.may_load(&deps.storage).unwrap().is_none());
+        assert_eq!(response.messages.len(), 1);
+        assert_eq!(response.messages[0].id, PUSD_WITHDRAW_REPLY_ID);
+        let CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr,
+            funds,
+            ..
+        }) = &response.messages[0].msg
+        else {
+            panic!("not a pusd_manager withdrawal");
+        };
+        assert_eq!(contract_addr, pusd_manager.as_str());
+        assert_eq!(funds, &vec![Coin::new(480u128, &pusd_denom)]);
+        let withdrawal = PENDING_WITHDRAWALS.front(&deps.storage).unwrap().unwrap();
+        assert_eq!(withdrawal.amount, Uint128::new(480));
+    }
+
+    #[test]
+    fn routes_must_be_connected() {
+        let route = [
+            hop("ugrain", "uatom"),
+            hop("uatom", "uosmo"),
+            hop("uusdc", "upusd"),
+        ];
+        let err = validate_route(&route, &[Coin::new(100u128, "ugrain")]).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::BrokenRoute { index: 2, ref offer, ref ask }
+                if offer == "uusdc" && ask == "uosmo"
+        ));
+        let err = validate_route(&[], &[Coin::new(100u128, "ugrain")]).unwrap_err();
+        assert!(matches!(err, ContractError::EmptyRoute {}));
+        let long_route = vec![hop("ugrain", "ugrain"); MAX_SWAP_OPERATIONS + 1];
+        let err = validate_route(&long_route, &[Coin::new(100u128, "ugrain")]).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::TooManyHops { hops, max }
+                if hops == MAX_SWAP_OPERATIONS + 1 && max == MAX_SWAP_OPERATIONS
+        ));
+        validate_route(&route[..2], &[Coin::new(100u128, "ugrain")]).unwrap();
+    }
+
+    #[test]
+    fn funds_must_match_the_first_offer() {
+        let route = [hop("ugrain", "upusd")];
+        for funds in [
+            vec![],
+            vec![Coin::new(100u128, "uatom")],
+            vec![Coin::new(0u128, "ugrain")],
+            vec![Coin::new(100u128, "ugrain"), Coin::new(100u128, "uatom")],
+        ] {
+            let err = validate_route(&route, &funds).unwrap_err();
+            assert!(matches!(
+                err,
+                ContractError::FundsMismatch { ref expected } if expected == "ugrain"
+            ));
+        }
+
+        // a route offering a CW20 token cannot be paid with native funds
+        let token = MockApi::default().addr_make("token");
+        let route = [SwapOperation::AstroSwap {
+            offer_asset_info: AssetInfo::Token {
+                contract_addr: token.clone(),
+            },
+            ask_asset_info: native("upusd"),
+        }];
+        let err = validate_route(&route, &[Coin::new(100u128, "ugrain")]).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::FundsMismatch { ref expected } if expected == token.as_str()
+        ));
+    }
+
+    #[test]
+    fn cw20_routes_must_offer_a_token() {
+        let token = MockApi::default().addr_make("token");
+        let route = [SwapOperation::AstroSwap {
+            offer_asset_info: AssetInfo::Token {
+                contract_addr: token.clone(),
+            },
+            ask_asset_info: native("upusd"),
+        }];
+        let offer = validate_cw20_route(&route, Uint128::new(100)).unwrap();
+        assert_eq!(offer.amount, Uint128::new(100));
+        let err = validate_cw20_route(&route, Uint128::zero()).unwrap_err();
+        assert!(matches!(err, ContractError::FundsMismatch { .. }));
+        let err = validate_cw20_route(&[hop("ugrain", "upusd")], Uint128::new(100)).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::FundsMismatch { ref expected } if expected == "ugrain"
+        ));
+    }
+
+    #[test]
+    fn holdings_are_paged_over_registered_assets() {
+        let mut deps = mock_dependencies();
+        let info = instantiate_owner(&mut deps);
+        let env = mock_env();
+        let token = deps.api.addr_make("token");
+        for denom in ["ugrain", "uusdc"] {
+            execute(
+                deps.as_mut(),
+                env.clone(),
+                info.clone(),
+                ExecuteMsg::SetNativeDenom {
+                    denom: denom.to_string(),
+                    registered: true,
+                },
+            )
+            .unwrap();
+        }
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            info.clone(),
+            ExecuteMsg::SetCw20Token {
+                contract_addr: token.to_string(),
+                registered: true,
+            },
+        )
+        .unwrap();
+        // unregistered denoms are not reported
+        deps.querier.bank.update_balance(
+            &env.contract.address,
+            vec![Coin::new(5u128, "ugrain"), Coin::new(7u128, "uatom")],
+        );
+        deps.querier.update_wasm(|query| {
+            let WasmQuery::Smart { msg, .. } = query else {
+                panic!("unexpected query {query:?}");
+            };
+            let ExternalQueryMsg::Balance { .. } = from_json(msg).unwrap() else {
+                panic!("unexpected query {msg:?}");
+            };
+            let response = to_json_binary(&BalanceResponse {
+                balance: Uint128::new(9),
+            });
+            SystemResult::Ok(ContractResult::Ok(response.unwrap()))
+        });
+        let holdings = |deps: &MockDeps, start_after: Option<AssetInfo>| -> Vec<Asset> {
+            from_json(
+                query(
+                    deps.as_ref(),
+                    mock_env(),
+                    QueryMsg::GetHoldings {
+                        start_after,
+                        limit: Some(2),
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap()
+        };
+
+        let page = holdings(&deps, None);
+        assert_eq!(
+            page,
+            vec![
+                Asset {
+                    info: native("ugrain"),
+                    amount: Uint128::new(5),
+                },
+                Asset {
+                    info: native("uusdc"),
+                    amount: Uint128::zero(),
+                },
+            ]
+        );
+        let page = holdings(&deps, Some(page[1].info.clone()));
+        let cw20 = Asset {
+            info: AssetInfo::Token {
+                contract_addr: token.clone(),
+            },
+            amount: Uint128::new(9),
+        };
+        assert_eq!(page, vec![cw20.clone()]);
+        assert!(holdings(&deps, Some(cw20.info)).is_empty());
+    }
+}
@@ -1,10 +1,174 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{Decimal, StdError, Uint128};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum ContractError {
     #[error("{0}")]
     Std(#[from] StdError),
+
+    #[error("Swap route is empty")]
+    EmptyRoute {},
+
+    #[error("Swap route has {hops} operations, the maximum is {max}")]
+    TooManyHops { hops: usize, max: usize },
+
+    #[error("Broken swap route at index {index}: offer asset {offer} does not match previous ask asset {ask}")]
+    BrokenRoute {
+        index: usize,
+        offer: String,
+        ask: String,
+    },
+
+    #[error("Funds must be a single non-zero coin of the first offer asset {expected}")]
+    FundsMismatch { expected: String },
+
+    #[error("Max deviation must be less than 1")]
+    InvalidMaxDeviation {},
+
+    #[error("Token decimals must be at most {max}, got {decimals}")]
+    InvalidDecimals { decimals: u8, max: u8 },
+
+    #[error("Oracle price is stale, last updated at {updated_at}")]
+    StaleOraclePrice { updated_at: u64 },
+
+    #[error(
+        "Simulated return {simulated} deviates too far from oracle expected return {expected}"
+    )]
+    PriceDeviation {
+        expected: String,
+        simulated: Uint128,
+    },
+
+    #[error("Invalid schedule: {reason}")]
+    InvalidSchedule { reason: String },
+
+    #[error("Schedule is not due until {next_execution}")]
+    ScheduleNotDue { next_execution: u64 },
+
+    #[error("Schedule ended at {end_time}")]
+    ScheduleEnded { end_time: u64 },
+
+    #[error("Unknown reply id {id}")]
+    UnknownReplyId { id: u64 },
+
+    #[error("Invalid order: {reason}")]
+    InvalidOrder { reason: String },
+
+    #[error("Order expired at {expiry}")]
+    OrderExpired { expiry: u64 },
+
+    #[error("Price {price} is above the target price {target_price}")]
+    PriceAboveTarget {
+        price: Decimal,
+        target_price: Decimal,
+    },
+
+    #[error("Split exchange has no legs")]
+    EmptySplit {},
+
+    #[error("Split leg {index} does not end in the same ask asset as the last leg")]
+    SplitAskMismatch { index: usize },
+
+    #[error("Split exchange received {received}, less than the minimum {minimum}")]
+    SplitMinimumNotMet { received: Uint128, minimum: Uint128 },
+
+    #[error("Asset {asset} cannot be bridged")]
+    NotBridgeable { asset: String },
+
+    #[error("Swap route must end in {expected}, not {actual}")]
+    AskAssetMismatch { expected: String, actual: String },
+
+    #[error("Denom {denom} is not bridgeable to {chain_reference_id}")]
+    DenomNotBridgeable {
+        denom: String,
+        chain_reference_id: String,
+    },
+
+    #[error("Insufficient {denom} balance: {balance}, required {required}")]
+    InsufficientBalance {
+        denom: String,
+        balance: Uint128,
+        required: Uint128,
+    },
+
+    #[error("Skyway transaction {transaction_id} is not recorded")]
+    UnknownTransaction { transaction_id: u64 },
+
+    #[error("Skyway transaction {transaction_id} is not pending")]
+    TransferNotPending { transaction_id: u64 },
+
+    #[error("Skyway transaction {transaction_id} is already recorded")]
+    TransactionAlreadyRecorded { transaction_id: u64 },
+
+    #[error("Unresolved transfer {id} is not recorded")]
+    UnknownUnresolvedTransfer { id: u64 },
+
+    #[error("Rate limit window must be positive")]
+    InvalidRateLimit {},
+
+    #[error("Loosening or removing a rate limit requires sudo")]
+    RateLimitLoosened {},
+
+    #[error("Rate limit exceeded for {denom} to {chain_id}, remaining {remaining}")]
+    RateLimitExceeded {
+        chain_id: String,
+        denom: String,
+        remaining: Uint128,
+    },
+
+    #[error("Recipient {recipient} is not allowed on {chain_id}")]
+    RecipientNotAllowed { chain_id: String, recipient: String },
+
+    #[error("No default pusd_manager is configured")]
+    NoDefaultPusdManager {},
+
+    #[error("pusd_manager {pusd_manager} is not registered")]
+    UnregisteredPusdManager { pusd_manager: String },
+
+    #[error("PUSD withdrawal {nonce} is not recorded")]
+    UnknownWithdrawal { nonce: u64 },
+
+    #[error("PUSD withdrawal {nonce} is not pending")]
+    WithdrawalNotPending { nonce: u64 },
+
+    #[error("PUSD withdrawal {nonce} is already recorded")]
+    WithdrawalAlreadyRecorded { nonce: u64 },
+
+    #[error("Unresolved withdrawal {id} is not recorded")]
+    UnknownUnresolvedWithdrawal { id: u64 },
+
+    #[error("CW20 token {contract_addr} is not registered")]
+    UnregisteredCw20Token { contract_addr: String },
+
+    #[error("Deposit without funds")]
+    EmptyDeposit {},
+
+    #[error("Invalid distribution: {reason}")]
+    InvalidDistribution { reason: String },
+
+    #[error("Unknown route {route_id}")]
+    UnknownRoute { route_id: String },
+
+    #[error("Bounty {bounty} exceeds the cap of {cap}")]
+    BountyExceedsCap { bounty: String, cap: Uint128 },
+
+    #[error("Unknown pre-approval {id}")]
+    UnknownPreApproval { id: u64 },
+
+    #[error("Pre-approval cannot be executed before {not_before}")]
+    PreApprovalNotReady { not_before: u64 },
+
+    #[error("{0}")]
+    Semver(#[from] semver::Error),
+
+    #[error("Cannot migrate {actual} to {expected}")]
+    WrongContract { expected: String, actual: String },
+
+    #[error("Cannot downgrade from {stored} to {version}")]
+    CannotDowngrade { stored: String, version: String },
+
+    #[error("Contract is paused")]
+    Paused {},
     // Add any other custom errors you like here.
     // Look at https://docs.rs/thiserror/1.0.21/thiserror/ for details.
 }
@@ -0,0 +1,91 @@
+use cosmwasm_std::Attribute;
+
+/// The attributes every action adds to the contract's `wasm` event.
+///
+/// `action` and `sender` are always emitted. The other fields are emitted only when they
+/// apply to the action, always under the same key and in the order below, followed by any
+/// action-specific attributes. Sudo messages and replies report the contract itself as
+/// `sender`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ActionEvent {
+    pub action: String,
+    pub sender: String,
+    /// The destination chain: a Skyway chain reference id or an EVM chain id
+    pub chain_id: Option<String>,
+    /// The Paloma scheduler job the action runs
+    pub job_id: Option<String>,
+    /// The amount moved as `<amount><asset>`, comma-separated for several assets, where the
+    /// asset is a native denom, a CW20 contract or an EVM token address
+    pub amount: Option<String>,
+    pub recipient: Option<String>,
+    /// The nonce of a PUSD withdrawal or of a compass `send_token` call
+    pub nonce: Option<String>,
+    pub attributes: Vec<Attribute>,
+}
+
+impl ActionEvent {
+    pub fn new(action: impl ToString, sender: impl ToString) -> Self {
+        ActionEvent {
+            action: action.to_string(),
+            sender: sender.to_string(),
+            ..ActionEvent::default()
+        }
+    }
+
+    pub fn chain_id(mut self, chain_id: impl ToString) -> Self {
+        self.chain_id = Some(chain_id.to_string());
+        self
+    }
+
+    pub fn job_id(mut self, job_id: impl ToString) -> Self {
+        self.job_id = Some(job_id.to_string());
+        self
+    }
+
+    pub fn amount(mut self, amount: impl ToString) -> Self {
+        self.amount = Some(amount.to_string());
+        self
+    }
+
+    pub fn recipient(mut self, recipient: impl ToString) -> Self {
+        self.recipient = Some(recipient.to_string());
+        self
+    }
+
+    pub fn nonce(mut self, nonce: impl ToString) -> Self {
+        self.nonce = Some(nonce.to_string());
+        self
+    }
+
+    /// Adds an action-specific attribute
+    pub fn attribute(mut self, key: &str, value: impl ToString) -> Self {
+        self.attributes.push(Attribute::new(key, value.to_string()));
+        self
+    }
+}
+
+impl IntoIterator for ActionEvent {
+    type Item = Attribute;
+    type IntoIter = std::vec::IntoIter<Attribute>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let mut attributes = vec![
+            Attribute::new("action", self.action),
+            Attribute::new("sender", self.sender),
+        ];
+        let optional = [
+            ("chain_id", self.chain_id),
+            ("job_id", self.job_id),
+            ("amount", self.amount),
+            ("recipient", self.recipient),
+            ("nonce", self.nonce),
+        ];
+        for (key, value) in optional {
+            if let Some(value) = value {
+                attributes.push(Attribute::new(key, value));
+            }
+        }
+        attributes.extend(self.attributes);
+        attributes.into_iter()
+    }
+}
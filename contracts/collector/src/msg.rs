@@ -1,7 +1,13 @@
+use std::fmt;
+
 #[allow(unused_imports)]
-use crate::state::State;
+use crate::state::{
+    ActionKind, DistributionShare, OutgoingTransfer, PreApproval, PreApprovedAction, PriceGuard,
+    PurchaseOrder, PurchaseRoute, PusdWithdrawal, Schedule, State, SweepRoute, UnresolvedTransfer,
+    UnresolvedWithdrawal,
+};
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use cosmwasm_std::{Addr, Coin, CustomMsg, Decimal, Uint128};
+use cosmwasm_std::{Addr, Binary, Coin, CustomMsg, Decimal, Uint128, Uint256};
 
 #[cw_serde]
 pub struct InstantiateMsg {
@@ -9,7 +15,14 @@ pub struct InstantiateMsg {
 }
 
 #[cw_serde]
-pub struct MigrateMsg {}
+pub struct MigrateMsg {
+    /// Replaces the allowlist delay
+    pub allowlist_delay: Option<u64>,
+    /// Registers a pusd_manager as the default one
+    pub pusd_manager: Option<String>,
+    /// The PUSD denom of `pusd_manager`, defaults to factory/<pusd_manager>/upusd
+    pub pusd_denom: Option<String>,
+}
 
 #[cw_serde]
 pub enum ExecuteMsg {
@@ -20,18 +33,111 @@ pub enum ExecuteMsg {
         to: Option<String>,
         max_spread: Option<Decimal>,
         funds: Vec<Coin>,
+        /// Spends this amount of a held CW20 offer token instead of `funds`
+        cw20_amount: Option<Uint128>,
+    },
+    /// CW20 tokens sent to the collector, with a `Cw20HookMsg` as the embedded message
+    Receive(Cw20ReceiveMsg),
+    ExchangeSplit {
+        /// The routes to swap through, all ending in the same ask asset
+        legs: Vec<SplitLeg>,
+        /// The minimum amount of the ask asset received across all legs
+        total_minimum_receive: Uint128,
+        max_spread: Option<Decimal>,
+    },
+    PurchaseAndBridge {
+        dex_router: Addr,
+        /// The swap route, ending in a bridgeable native token
+        operations: Vec<SwapOperation>,
+        minimum_receive: Option<Uint128>,
+        max_spread: Option<Decimal>,
+        funds: Vec<Coin>,
+        /// The EVM address to send the purchased tokens to
+        recipient: String,
+        chain_reference_id: String,
+    },
+    PurchasePusdAndWithdraw {
+        dex_router: Addr,
+        /// The swap route, ending in the PUSD denom of `pusd_manager`
+        operations: Vec<SwapOperation>,
+        minimum_receive: Option<Uint128>,
+        max_spread: Option<Decimal>,
+        funds: Vec<Coin>,
+        /// The address of a registered pusd_manager CW contract, defaults to the configured one
+        pusd_manager: Option<Addr>,
+        /// The chain id of the chain to withdraw to
+        chain_id: String,
+        /// The EVM address to send the funds to
+        recipient: String,
     },
     SendToEvm {
         recipient: String,
-        amount: String,
+        amount: Coin,
+        chain_reference_id: String,
+    },
+    SetBridgeableDenom {
         chain_reference_id: String,
+        denom: String,
+        bridgeable: bool,
+    },
+    /// Funds the collector with the attached coins, recorded for the sender
+    Deposit {},
+    /// Sends native coins held by the collector to a Paloma address
+    Withdraw {
+        recipient: String,
+        coins: Vec<Coin>,
+    },
+    /// Sends the whole balance of each of `denoms` to a Paloma address
+    WithdrawAll {
+        recipient: String,
+        denoms: Vec<String>,
+    },
+    /// Sends a CW20 token held by the collector to a Paloma address
+    WithdrawCw20 {
+        recipient: String,
+        token: String,
+        amount: Uint128,
+    },
+    /// Replaces the distribution table. Weights must sum to 10000 bps; an empty table
+    /// disables `Distribute`.
+    SetDistribution {
+        shares: Vec<DistributionShare>,
+    },
+    /// Splits the contract's whole balance of `denom` according to the distribution table
+    Distribute {
+        denom: String,
+    },
+    /// Registers a native denom so that it is reported in the holdings
+    SetNativeDenom {
+        denom: String,
+        registered: bool,
+    },
+    /// Registers a CW20 token so that it is reported in the holdings
+    SetCw20Token {
+        contract_addr: String,
+        registered: bool,
     },
     CancelTx {
         transaction_id: u64,
     },
+    /// Records a pending Skyway transfer the collector does not know of, such as one sent
+    /// before transfers were recorded, so that it can be cancelled
+    RegisterTransfer {
+        transaction_id: u64,
+        chain_reference_id: String,
+        /// The EVM address the funds are sent to
+        recipient: String,
+        amount: Coin,
+    },
+    /// Records an unresolved transfer under the transaction id Skyway assigned to it, so
+    /// that it can be cancelled
+    ResolveTransfer {
+        id: u64,
+        transaction_id: u64,
+    },
     WithdrawPusd {
-        /// The address of the pusd_manager CW contract
-        pusd_manager: Addr,
+        /// The address of a registered pusd_manager CW contract, defaults to the configured one
+        pusd_manager: Option<Addr>,
         /// The chain id of the chain to withdraw from
         chain_id: String,
         /// The EVM address to send the funds to
@@ -40,17 +146,215 @@ pub enum ExecuteMsg {
         amount: Uint128,
     },
     ReWithdrawPusd {
-        /// The address of the pusd_manager CW contract
-        pusd_manager: Addr,
+        /// The address of a registered pusd_manager CW contract, defaults to the configured one
+        pusd_manager: Option<Addr>,
         /// The nonce of the withdrawal to re-withdraw
         nonce: u64,
     },
     CancelWithdrawPusd {
-        /// The address of the pusd_manager CW contract
-        pusd_manager: Addr,
+        /// The address of a registered pusd_manager CW contract, defaults to the configured one
+        pusd_manager: Option<Addr>,
         /// The nonce of the withdrawal to cancel
         nonce: u64,
     },
+    /// Marks a pending withdrawal as completed once it has arrived on the target chain
+    CompleteWithdrawPusd {
+        /// The address of a registered pusd_manager CW contract, defaults to the configured one
+        pusd_manager: Option<Addr>,
+        /// The nonce of the withdrawal to complete
+        nonce: u64,
+    },
+    /// Records a pending withdrawal the contract does not know of, such as one made before
+    /// withdrawals were recorded, so that it can be re-withdrawn, cancelled or completed
+    RegisterWithdrawPusd {
+        /// The address of a registered pusd_manager CW contract, defaults to the configured one
+        pusd_manager: Option<Addr>,
+        /// The nonce the pusd_manager assigned to the withdrawal
+        nonce: u64,
+        /// The chain id of the chain withdrawn from
+        chain_id: String,
+        /// The EVM address the funds are sent to
+        recipient: String,
+        /// The PUSD amount withdrawn
+        amount: Uint128,
+    },
+    /// Records an unresolved withdrawal under the nonce the pusd_manager assigned to it
+    ResolveWithdrawPusd {
+        id: u64,
+        nonce: u64,
+    },
+    UpdateConfig {
+        /// The delay in seconds before a new allowlisted recipient can receive funds
+        allowlist_delay: Option<u64>,
+    },
+    /// Enforces or lifts the recipient allowlist of a chain
+    SetAllowlistEnabled {
+        chain_id: String,
+        enabled: bool,
+    },
+    /// Allows transfers to an EVM address once the allowlist delay has passed
+    AddAllowedRecipient {
+        chain_id: String,
+        recipient: String,
+    },
+    RemoveAllowedRecipient {
+        chain_id: String,
+        recipient: String,
+    },
+    /// Adds or tightens a rate limit. Raising `max_amount` or shortening `window` requires
+    /// `SudoMsg::SetRateLimit`.
+    SetRateLimit {
+        /// The chain id or chain reference id of the destination chain
+        chain_id: String,
+        denom: String,
+        /// The maximum amount sent out within one window
+        max_amount: Uint128,
+        /// The window length in seconds
+        window: u64,
+    },
+    SetPriceGuard {
+        /// The first offer asset of the guarded routes
+        offer_asset_info: AssetInfo,
+        /// The final ask asset of the guarded routes
+        ask_asset_info: AssetInfo,
+        /// The address of the Band-style standard reference oracle contract
+        oracle: String,
+        /// The oracle symbol of the offer asset
+        base_symbol: String,
+        /// The oracle symbol of the ask asset
+        quote_symbol: String,
+        /// The decimals of the offer asset
+        offer_decimals: u8,
+        /// The decimals of the ask asset
+        ask_decimals: u8,
+        /// The maximum deviation of the simulated swap price from the oracle price
+        max_deviation: Decimal,
+        /// The maximum age of the oracle price in seconds
+        max_staleness: u64,
+    },
+    RemovePriceGuard {
+        offer_asset_info: AssetInfo,
+        ask_asset_info: AssetInfo,
+    },
+    /// Registers a vetted purchase route under `route_id`, replacing any route of that id
+    SetRoute {
+        route_id: String,
+        dex_router: Addr,
+        operations: Vec<SwapOperation>,
+        /// The slippage used for every swap through the route
+        max_spread: Option<Decimal>,
+    },
+    RemoveRoute {
+        route_id: String,
+    },
+    /// Swaps `amount` of the route's offer asset held by the collector through the route
+    ExchangeByRoute {
+        route_id: String,
+        amount: Uint128,
+    },
+    /// Registers the route that sweeps the first offer asset of `operations` into the
+    /// final ask asset
+    SetSweepRoute {
+        dex_router: Addr,
+        operations: Vec<SwapOperation>,
+        max_spread: Option<Decimal>,
+    },
+    RemoveSweepRoute {
+        offer_asset_info: AssetInfo,
+        target: AssetInfo,
+    },
+    /// Swaps the whole balance of every asset with a sweep route to `target`, when its
+    /// simulated return is at least `min_value` of `target`
+    SweepDust {
+        target: AssetInfo,
+        min_value: Uint128,
+    },
+    CreateSchedule {
+        /// The address of the DEX router used for every tranche
+        dex_router: Addr,
+        /// The swap route of every tranche
+        operations: Vec<SwapOperation>,
+        /// The offer asset amount swapped per tranche
+        amount_per_interval: Coin,
+        /// The minimum number of seconds between tranches
+        interval: u64,
+        /// The time of the first tranche, defaults to now
+        start_time: Option<u64>,
+        /// The time after which no more tranches are executed
+        end_time: u64,
+        max_spread: Option<Decimal>,
+    },
+    CancelSchedule {
+        schedule_id: u64,
+    },
+    /// Executes the next tranche of a schedule once its interval has elapsed. Callable by anyone.
+    ExecuteDue {
+        schedule_id: u64,
+    },
+    CreateOrder {
+        /// The address of the DEX router
+        dex_router: Addr,
+        /// The swap route of the order
+        operations: Vec<SwapOperation>,
+        /// The offer asset amount to swap
+        offer: Coin,
+        /// The maximum price, in offer asset per ask asset
+        target_price: Decimal,
+        /// The time after which the order can no longer be executed
+        expiry: u64,
+        max_spread: Option<Decimal>,
+    },
+    CancelOrder {
+        order_id: u64,
+    },
+    /// Executes an order if the simulated price is at or below its target. Callable by anyone.
+    ExecuteOrder {
+        order_id: u64,
+    },
+    /// Removes the expired orders among a page of orders. Callable by anyone.
+    PruneExpiredOrders {
+        /// The order id to scan after, the `last_order_id` of the previous call
+        start_after: Option<u64>,
+        /// The number of orders to scan
+        limit: Option<u32>,
+    },
+    /// Registers a pusd_manager CW contract, optionally as the default one
+    SetPusdManager {
+        pusd_manager: String,
+        /// The PUSD denom, defaults to factory/<pusd_manager>/upusd
+        denom: Option<String>,
+        is_default: bool,
+    },
+    RemovePusdManager {
+        pusd_manager: String,
+    },
+    /// Approves an exact action for keepers to trigger from `not_before` on
+    PreApprove {
+        action: PreApprovedAction,
+        /// Unix time in seconds from which the action may be executed
+        not_before: u64,
+        /// Paid to the keeper from the collector's balance, up to the action's bounty cap
+        bounty: Option<Coin>,
+        /// Lets any address trigger the action, not only registered keepers
+        permissionless: bool,
+    },
+    RevokePreApproval {
+        id: u64,
+    },
+    /// Executes a pre-approved action once, paying its bounty to the caller
+    ExecutePreApproved {
+        id: u64,
+    },
+    SetKeeper {
+        keeper: String,
+        enabled: bool,
+    },
+    /// Sets the highest bounty in `denom` paid for an action kind, removing it when `None`
+    SetBountyCap {
+        kind: ActionKind,
+        denom: String,
+        cap: Option<Uint128>,
+    },
     AddOwner {
         owners: Vec<String>,
     },
@@ -59,6 +363,13 @@ pub enum ExecuteMsg {
     },
 }
 
+#[cw_serde]
+pub struct SplitLeg {
+    pub dex_router: Addr,
+    pub operations: Vec<SwapOperation>,
+    pub offer_amount: Uint128,
+}
+
 #[cw_serde]
 pub enum SwapOperation {
     AstroSwap {
@@ -69,6 +380,22 @@ pub enum SwapOperation {
     },
 }
 
+impl SwapOperation {
+    pub fn offer_asset_info(&self) -> &AssetInfo {
+        match self {
+            SwapOperation::AstroSwap {
+                offer_asset_info, ..
+            } => offer_asset_info,
+        }
+    }
+
+    pub fn ask_asset_info(&self) -> &AssetInfo {
+        match self {
+            SwapOperation::AstroSwap { ask_asset_info, .. } => ask_asset_info,
+        }
+    }
+}
+
 #[cw_serde]
 #[derive(Hash, Eq)]
 pub enum AssetInfo {
@@ -78,6 +405,90 @@ pub enum AssetInfo {
     NativeToken { denom: String },
 }
 
+impl fmt::Display for AssetInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssetInfo::Token { contract_addr } => write!(f, "{contract_addr}"),
+            AssetInfo::NativeToken { denom } => write!(f, "{denom}"),
+        }
+    }
+}
+
+#[cw_serde]
+pub struct Asset {
+    pub info: AssetInfo,
+    pub amount: Uint128,
+}
+
+impl From<Coin> for Asset {
+    fn from(coin: Coin) -> Self {
+        Asset {
+            info: AssetInfo::NativeToken { denom: coin.denom },
+            amount: coin.amount,
+        }
+    }
+}
+
+impl fmt::Display for Asset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.amount, self.info)
+    }
+}
+
+/// Mirror of the cw20 `Cw20ReceiveMsg`
+#[cw_serde]
+pub struct Cw20ReceiveMsg {
+    pub sender: String,
+    pub amount: Uint128,
+    pub msg: Binary,
+}
+
+#[cw_serde]
+pub enum Cw20HookMsg {
+    /// Keeps the received tokens in the collector
+    Deposit {},
+    /// Swaps the received tokens through the router. Only accepted from owners.
+    Swap {
+        dex_router: Addr,
+        operations: Vec<SwapOperation>,
+        minimum_receive: Option<Uint128>,
+        to: Option<String>,
+        max_spread: Option<Decimal>,
+    },
+}
+
+/// Messages only chain governance can send
+#[cw_serde]
+pub enum SudoMsg {
+    /// Replaces every owner
+    SetOwners {
+        owners: Vec<String>,
+    },
+    /// Rejects every execute message until `Unpause`
+    Pause {},
+    Unpause {},
+    /// Cancels a Skyway transfer, including transfers the collector did not record
+    CancelTx {
+        transaction_id: u64,
+    },
+    /// Cancels a PUSD withdrawal, including withdrawals the contract did not record
+    CancelWithdrawPusd {
+        pusd_manager: Addr,
+        nonce: u64,
+    },
+    /// Sets a rate limit, including limits looser than the current one
+    SetRateLimit {
+        chain_id: String,
+        denom: String,
+        max_amount: Uint128,
+        window: u64,
+    },
+    RemoveRateLimit {
+        chain_id: String,
+        denom: String,
+    },
+}
+
 #[cw_serde]
 pub enum ExternalExecuteMsg {
     ExecuteSwapOperations {
@@ -90,6 +501,17 @@ pub enum ExternalExecuteMsg {
         chain_id: String,
         recipient: String,
     },
+    /// CW20 transfer
+    Transfer {
+        recipient: String,
+        amount: Uint128,
+    },
+    /// CW20 send, carrying `msg` to the `contract`'s receive hook
+    Send {
+        contract: String,
+        amount: Uint128,
+        msg: Binary,
+    },
     // ReWithdraw PUSD by nonce
     ReWithdraw {
         nonce: u64,
@@ -100,6 +522,42 @@ pub enum ExternalExecuteMsg {
     },
 }
 
+#[cw_serde]
+pub enum ExternalQueryMsg {
+    /// Router query for the expected return of a swap route
+    SimulateSwapOperations {
+        offer_amount: Uint128,
+        operations: Vec<SwapOperation>,
+    },
+    /// Band standard reference query for the price of base in quote
+    GetReferenceData {
+        base_symbol: String,
+        quote_symbol: String,
+    },
+    /// CW20 balance query
+    Balance { address: String },
+    /// pusd_manager query for a withdrawal by nonce
+    GetWithdrawal { nonce: u64 },
+}
+
+#[cw_serde]
+pub struct SimulateSwapOperationsResponse {
+    pub amount: Uint128,
+}
+
+#[cw_serde]
+pub struct BalanceResponse {
+    pub balance: Uint128,
+}
+
+#[cw_serde]
+pub struct ReferenceData {
+    /// The price of base in quote, multiplied by 1e18
+    pub rate: Uint256,
+    pub last_updated_base: u64,
+    pub last_updated_quote: u64,
+}
+
 #[cw_serde]
 pub enum PalomaMsg {
     /// Message struct for tokenfactory calls.
@@ -126,6 +584,166 @@ pub struct CancelTx {
 pub enum QueryMsg {
     #[returns(State)]
     GetState {},
+    #[returns(PriceGuard)]
+    GetPriceGuard {
+        offer_asset_info: AssetInfo,
+        ask_asset_info: AssetInfo,
+    },
+    #[returns(Vec<String>)]
+    GetBridgeableDenoms { chain_reference_id: String },
+    #[returns(PreApproval)]
+    GetPreApproval { id: u64 },
+    #[returns(Vec<PreApproval>)]
+    GetPreApprovals {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(Vec<Addr>)]
+    GetKeepers {},
+    #[returns(Vec<BountyCapInfo>)]
+    GetBountyCaps {},
+    #[returns(PurchaseRoute)]
+    GetRoute { route_id: String },
+    #[returns(Vec<PurchaseRoute>)]
+    GetRoutes {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    #[returns(Vec<SweepRoute>)]
+    GetSweepRoutes { target: AssetInfo },
+    #[returns(Vec<DistributionShare>)]
+    GetDistribution {},
+    #[returns(Vec<DepositInfo>)]
+    GetDepositor { addr: String },
+    #[returns(Vec<String>)]
+    GetNativeDenoms {},
+    #[returns(Vec<Addr>)]
+    GetCw20Tokens {},
+    /// Balances of the registered native denoms followed by those of the registered CW20
+    /// tokens, one page at a time
+    #[returns(Vec<Asset>)]
+    GetHoldings {
+        start_after: Option<AssetInfo>,
+        limit: Option<u32>,
+    },
+    #[returns(Asset)]
+    GetHolding { asset: AssetInfo },
+    #[returns(Vec<PusdWithdrawal>)]
+    GetOutstandingPusdWithdrawals {
+        /// Defaults to the configured pusd_manager
+        pusd_manager: Option<Addr>,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(Vec<UnresolvedWithdrawal>)]
+    GetUnresolvedPusdWithdrawals {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(PusdManagerWithdrawal)]
+    GetPusdWithdrawal {
+        /// Defaults to the configured pusd_manager
+        pusd_manager: Option<Addr>,
+        nonce: u64,
+    },
+    #[returns(Coin)]
+    GetPusdBalance {
+        /// Defaults to the configured pusd_manager
+        pusd_manager: Option<Addr>,
+    },
+    #[returns(Vec<PusdManagerInfo>)]
+    GetPusdManagers {},
+    #[returns(AllowlistResponse)]
+    GetAllowlist { chain_id: String },
+    #[returns(RateLimitResponse)]
+    GetRateLimit { chain_id: String, denom: String },
+    #[returns(OutgoingTransfer)]
+    GetOutgoingTransfer { transaction_id: u64 },
+    #[returns(Vec<OutgoingTransfer>)]
+    GetOutgoingTransfers {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(Vec<UnresolvedTransfer>)]
+    GetUnresolvedTransfers {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(Schedule)]
+    GetSchedule { schedule_id: u64 },
+    #[returns(Vec<Schedule>)]
+    GetSchedules {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(PurchaseOrder)]
+    GetOrder { order_id: u64 },
+    #[returns(Vec<PurchaseOrder>)]
+    GetOrders {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+}
+
+#[cw_serde]
+pub struct RateLimitResponse {
+    pub max_amount: Uint128,
+    pub window: u64,
+    pub used: Uint128,
+    pub remaining: Uint128,
+}
+
+#[cw_serde]
+pub struct AllowlistResponse {
+    pub enabled: bool,
+    /// When a requested disable takes effect
+    pub disabled_from: Option<u64>,
+    pub recipients: Vec<AllowedRecipientInfo>,
+}
+
+#[cw_serde]
+pub struct AllowedRecipientInfo {
+    pub recipient: String,
+    pub added_at_height: u64,
+    pub active_from: u64,
+}
+
+#[cw_serde]
+pub struct BountyCapInfo {
+    pub kind: ActionKind,
+    pub denom: String,
+    pub cap: Uint128,
+}
+
+/// A depositor's contributions of one denom
+#[cw_serde]
+pub struct DepositInfo {
+    pub denom: String,
+    pub deposited: Uint128,
+    /// The depositor's part of the deposits still held
+    pub remaining: Uint128,
+    /// The depositor's part of the deposits spent on purchases
+    pub purchased: Uint128,
+    /// The depositor's part of the deposits bridged out
+    pub bridged: Uint128,
+    /// The depositor's part of the deposits sent to Paloma addresses
+    pub withdrawn: Uint128,
+}
+
+/// A withdrawal as the pusd_manager reports it
+#[cw_serde]
+pub struct PusdManagerWithdrawal {
+    pub nonce: u64,
+    pub chain_id: String,
+    pub recipient: String,
+    pub amount: Uint128,
+}
+
+#[cw_serde]
+pub struct PusdManagerInfo {
+    pub pusd_manager: Addr,
+    pub denom: String,
+    pub is_default: bool,
 }
 
 impl CustomMsg for PalomaMsg {}
@@ -1,11 +1,371 @@
-use cosmwasm_std::Addr;
-use cw_storage_plus::Item;
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Addr, Coin, Decimal, Empty, Uint128};
+use cw_storage_plus::{Deque, Item, Map};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::msg::{AssetInfo, SwapOperation};
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
 pub struct State {
     pub owners: Vec<Addr>,
+    pub allowlist_delay: u64,
+    /// The pusd_manager used when a call does not name one
+    pub pusd_manager: Option<Addr>,
+    /// Set by governance to reject every execute message
+    pub paused: bool,
+    /// A lower `allowlist_delay` waiting out the delay it replaces
+    pub pending_allowlist_delay: Option<PendingAllowlistDelay>,
+}
+
+/// `State` as stored by 0.1.0
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct StateV0_1 {
+    pub owners: Vec<Addr>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct PendingAllowlistDelay {
+    pub allowlist_delay: u64,
+    pub effective_at: u64,
+}
+
+#[cw_serde]
+pub struct PriceGuard {
+    pub oracle: Addr,
+    pub base_symbol: String,
+    pub quote_symbol: String,
+    pub offer_decimals: u8,
+    pub ask_decimals: u8,
+    pub max_deviation: Decimal,
+    pub max_staleness: u64,
+}
+
+#[cw_serde]
+pub struct Schedule {
+    pub id: u64,
+    pub dex_router: Addr,
+    pub operations: Vec<SwapOperation>,
+    pub amount_per_interval: Coin,
+    pub interval: u64,
+    pub next_execution: u64,
+    pub end_time: u64,
+    pub max_spread: Option<Decimal>,
+    pub tranches_executed: u64,
+    pub total_offered: Uint128,
+    pub total_received: Uint128,
+}
+
+#[cw_serde]
+pub struct PurchaseOrder {
+    pub id: u64,
+    pub dex_router: Addr,
+    pub operations: Vec<SwapOperation>,
+    pub offer: Coin,
+    pub target_price: Decimal,
+    pub expiry: u64,
+    pub max_spread: Option<Decimal>,
+}
+
+#[cw_serde]
+pub struct PusdWithdrawal {
+    pub pusd_manager: Addr,
+    pub nonce: u64,
+    pub chain_id: String,
+    pub recipient: String,
+    pub amount: Uint128,
+    pub status: WithdrawalStatus,
+}
+
+#[cw_serde]
+pub enum WithdrawalStatus {
+    Pending,
+    Cancelled,
+    /// Arrived on the target chain, as confirmed by an owner
+    Completed,
+}
+
+/// A PUSD withdrawal dispatched as a submessage, waiting for its nonce.
+#[cw_serde]
+pub struct PendingWithdrawal {
+    pub pusd_manager: Addr,
+    pub chain_id: String,
+    pub recipient: String,
+    pub amount: Uint128,
+}
+
+/// A PUSD withdrawal whose reply carried no nonce. It stays here until an owner resolves
+/// it with the nonce the pusd_manager assigned.
+#[cw_serde]
+pub struct UnresolvedWithdrawal {
+    pub id: u64,
+    pub pusd_manager: Addr,
+    pub chain_id: String,
+    pub recipient: String,
+    pub amount: Uint128,
+}
+
+/// Deposits of one denom, attributed to depositors through shares
+#[cw_serde]
+#[derive(Default)]
+pub struct DepositPool {
+    /// Incremented whenever every deposit has been spent, voiding the older shares
+    pub generation: u64,
+    pub shares: Uint128,
+    /// Deposited tokens not yet spent or bridged out
+    pub remaining: Uint128,
+    /// Deposited tokens spent on purchases, per share
+    pub purchased_per_share: Decimal,
+    /// Deposited tokens bridged out, per share
+    pub bridged_per_share: Decimal,
+    /// Deposited tokens sent to Paloma addresses, per share
+    pub withdrawn_per_share: Decimal,
+}
+
+#[cw_serde]
+#[derive(Default)]
+pub struct DepositorShare {
+    pub generation: u64,
+    pub shares: Uint128,
+    pub deposited: Uint128,
+    pub purchased: Uint128,
+    pub bridged: Uint128,
+    pub withdrawn: Uint128,
+    /// The pool's per share amounts when the outflows above were last settled
+    pub purchased_checkpoint: Decimal,
+    pub bridged_checkpoint: Decimal,
+    pub withdrawn_checkpoint: Decimal,
+}
+
+/// An action approved by the owners for keepers to trigger
+#[cw_serde]
+pub enum PreApprovedAction {
+    Exchange {
+        dex_router: Addr,
+        operations: Vec<SwapOperation>,
+        minimum_receive: Option<Uint128>,
+        to: Option<String>,
+        max_spread: Option<Decimal>,
+        funds: Vec<Coin>,
+        cw20_amount: Option<Uint128>,
+    },
+    SendToEvm {
+        recipient: String,
+        amount: Coin,
+        chain_reference_id: String,
+    },
+    WithdrawPusd {
+        pusd_manager: Option<Addr>,
+        chain_id: String,
+        recipient: String,
+        amount: Uint128,
+    },
+}
+
+impl PreApprovedAction {
+    pub fn kind(&self) -> ActionKind {
+        match self {
+            PreApprovedAction::Exchange { .. } => ActionKind::Exchange,
+            PreApprovedAction::SendToEvm { .. } => ActionKind::SendToEvm,
+            PreApprovedAction::WithdrawPusd { .. } => ActionKind::WithdrawPusd,
+        }
+    }
+}
+
+#[cw_serde]
+#[derive(Copy)]
+pub enum ActionKind {
+    Exchange,
+    SendToEvm,
+    WithdrawPusd,
+}
+
+impl ActionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ActionKind::Exchange => "exchange",
+            ActionKind::SendToEvm => "send_to_evm",
+            ActionKind::WithdrawPusd => "withdraw_pusd",
+        }
+    }
+}
+
+#[cw_serde]
+pub struct PreApproval {
+    pub id: u64,
+    pub action: PreApprovedAction,
+    pub not_before: u64,
+    pub bounty: Option<Coin>,
+    pub permissionless: bool,
+}
+
+/// A vetted purchase route registered by the owners
+#[cw_serde]
+pub struct PurchaseRoute {
+    pub id: String,
+    pub offer_asset_info: AssetInfo,
+    pub ask_asset_info: AssetInfo,
+    pub dex_router: Addr,
+    pub operations: Vec<SwapOperation>,
+    pub max_spread: Option<Decimal>,
+}
+
+#[cw_serde]
+pub struct SweepRoute {
+    pub dex_router: Addr,
+    pub operations: Vec<SwapOperation>,
+    pub max_spread: Option<Decimal>,
+}
+
+/// A recipient of distributed proceeds
+#[cw_serde]
+pub enum DistributionTarget {
+    /// Bank send to a Paloma address
+    Native { address: Addr },
+    /// Skyway send to an EVM address
+    Evm {
+        recipient: String,
+        chain_reference_id: String,
+    },
+    /// PUSD withdrawal to an EVM address through a registered pusd_manager
+    Pusd {
+        pusd_manager: Addr,
+        chain_id: String,
+        recipient: String,
+    },
+}
+
+#[cw_serde]
+pub struct DistributionShare {
+    pub target: DistributionTarget,
+    /// Weight in basis points, all weights summing to 10000
+    pub weight_bps: u16,
+}
+
+#[cw_serde]
+pub struct AllowedRecipient {
+    pub added_at_height: u64,
+    pub active_from: u64,
+}
+
+/// Rolling-window outflow limit. `used` decays linearly over `window` seconds.
+#[cw_serde]
+pub struct RateLimit {
+    pub max_amount: Uint128,
+    pub window: u64,
+    pub used: Uint128,
+    pub updated_at: u64,
+}
+
+#[cw_serde]
+pub struct OutgoingTransfer {
+    pub transaction_id: u64,
+    pub recipient: String,
+    pub amount: Coin,
+    pub chain_reference_id: String,
+    pub status: TransferStatus,
+}
+
+#[cw_serde]
+pub enum TransferStatus {
+    Pending,
+    Cancelled,
+}
+
+/// A Skyway transfer whose `send_tx` reply carried no transaction id. It stays here until
+/// an owner resolves it with the id Skyway assigned.
+#[cw_serde]
+pub struct UnresolvedTransfer {
+    pub id: u64,
+    pub recipient: String,
+    pub amount: Coin,
+    pub chain_reference_id: String,
+}
+
+/// A Skyway transfer dispatched as a submessage, waiting for its transaction id.
+#[cw_serde]
+pub struct PendingTransfer {
+    pub recipient: String,
+    pub amount: Coin,
+    pub chain_reference_id: String,
+}
+
+/// Context of a swap dispatched as a submessage, read back in `reply` to measure
+/// the received amount.
+#[cw_serde]
+pub struct PendingSwap {
+    pub ask_asset_info: AssetInfo,
+    pub balance_before: Uint128,
+    pub purpose: SwapPurpose,
+}
+
+#[cw_serde]
+pub enum SwapPurpose {
+    Schedule {
+        schedule_id: u64,
+    },
+    Split {
+        total_minimum_receive: Uint128,
+    },
+    Bridge {
+        recipient: String,
+        chain_reference_id: String,
+    },
+    PusdWithdraw {
+        pusd_manager: Addr,
+        chain_id: String,
+        recipient: String,
+    },
 }
 
 pub const STATE: Item<State> = Item::new("state");
+pub const STATE_V0_1: Item<StateV0_1> = Item::new("state");
+// (chain reference id, denom) => bridgeable
+pub const BRIDGEABLE_DENOMS: Map<(&str, &str), Empty> = Map::new("bridgeable_denoms");
+// (chain id, denom) => outflow rate limit
+pub const RATE_LIMITS: Map<(&str, &str), RateLimit> = Map::new("rate_limits");
+// (offer asset, ask asset) => price guard
+pub const PRICE_GUARDS: Map<(String, String), PriceGuard> = Map::new("price_guards");
+pub const SCHEDULES: Map<u64, Schedule> = Map::new("schedules");
+pub const SCHEDULE_COUNT: Item<u64> = Item::new("schedule_count");
+pub const PENDING_SWAP: Item<PendingSwap> = Item::new("pending_swap");
+pub const ORDERS: Map<u64, PurchaseOrder> = Map::new("orders");
+pub const ORDER_COUNT: Item<u64> = Item::new("order_count");
+pub const OUTGOING_TRANSFERS: Map<u64, OutgoingTransfer> = Map::new("outgoing_transfers");
+// replies arrive in dispatch order, so pending transfers are matched first in, first out
+pub const PENDING_TRANSFERS: Deque<PendingTransfer> = Deque::new("pending_transfers");
+pub const UNRESOLVED_TRANSFERS: Map<u64, UnresolvedTransfer> = Map::new("unresolved_transfers");
+pub const UNRESOLVED_TRANSFER_COUNT: Item<u64> = Item::new("unresolved_transfer_count");
+// chain id => recipient allowlist enforced
+pub const ALLOWLIST_ENABLED: Map<&str, bool> = Map::new("allowlist_enabled");
+// chain id => time the allowlist stops being enforced, once the allowlist delay has passed
+pub const ALLOWLIST_DISABLED_FROM: Map<&str, u64> = Map::new("allowlist_disabled_from");
+// (chain id, lowercase EVM address) => allowed recipient
+pub const ALLOWED_RECIPIENTS: Map<(&str, &str), AllowedRecipient> = Map::new("allowed_recipients");
+// registered pusd_manager => PUSD denom
+pub const PUSD_MANAGERS: Map<&Addr, String> = Map::new("pusd_managers");
+// (pusd_manager, nonce) => withdrawal
+pub const PUSD_WITHDRAWALS: Map<(&Addr, u64), PusdWithdrawal> = Map::new("pusd_withdrawals");
+// replies arrive in dispatch order, so pending withdrawals are matched first in, first out
+pub const PENDING_WITHDRAWALS: Deque<PendingWithdrawal> = Deque::new("pending_withdrawals");
+pub const UNRESOLVED_WITHDRAWALS: Map<u64, UnresolvedWithdrawal> =
+    Map::new("unresolved_withdrawals");
+pub const UNRESOLVED_WITHDRAWAL_COUNT: Item<u64> = Item::new("unresolved_withdrawal_count");
+// registered native denoms, reported in the holdings
+pub const NATIVE_DENOMS: Map<&str, Empty> = Map::new("native_denoms");
+// registered CW20 tokens, reported in the holdings
+pub const CW20_TOKENS: Map<&Addr, Empty> = Map::new("cw20_tokens");
+// denom => deposit pool; CW20 tokens are keyed by contract address
+pub const DEPOSIT_POOLS: Map<&str, DepositPool> = Map::new("deposit_pools");
+// (denom, generation) => deposit pool as it was when its deposits ran out
+pub const SPENT_DEPOSIT_POOLS: Map<(&str, u64), DepositPool> = Map::new("spent_deposit_pools");
+pub const DEPOSITOR_SHARES: Map<(&Addr, &str), DepositorShare> = Map::new("depositor_shares");
+pub const DISTRIBUTION: Item<Vec<DistributionShare>> = Item::new("distribution");
+// (target, offer asset) => route sweeping the offer asset into the target
+pub const SWEEP_ROUTES: Map<(String, String), SweepRoute> = Map::new("sweep_routes");
+pub const ROUTES: Map<&str, PurchaseRoute> = Map::new("routes");
+pub const PRE_APPROVALS: Map<u64, PreApproval> = Map::new("pre_approvals");
+pub const PRE_APPROVAL_COUNT: Item<u64> = Item::new("pre_approval_count");
+pub const KEEPERS: Map<&Addr, Empty> = Map::new("keepers");
+// (action kind, denom) => highest bounty paid for the action
+pub const BOUNTY_CAPS: Map<(&str, &str), Uint128> = Map::new("bounty_caps");
@@ -3,34 +3,111 @@ use std::collections::BTreeMap;
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    to_json_binary, Binary, Coin, CosmosMsg, Deps, DepsMut, Env, MessageInfo, Response, StdResult,
-    WasmMsg,
+    to_json_binary, Addr, Binary, Coin, CosmosMsg, Deps, DepsMut, Env, Event, MessageInfo, Order,
+    Reply, Response, StdError, StdResult, Storage, SubMsg, Uint128, WasmMsg,
 };
-use cw2::set_contract_version;
+use cw2::{set_contract_version, ContractVersion, CONTRACT};
+use cw_storage_plus::Bound;
 use ethabi::{Address, Contract, Function, Param, ParamType, StateMutability, Token, Uint};
+use semver::Version;
 use std::str::FromStr;
 
 use crate::error::ContractError;
+use crate::events::ActionEvent;
 use crate::msg::{
-    ExecuteJob, ExecuteMsg, ExternalExecuteMsg, InstantiateMsg, MigrateMsg, PalomaMsg, QueryMsg,
+    AllowedRecipientInfo, AllowlistResponse, AssetInfo, ExecuteJob, ExecuteMsg, ExternalExecuteMsg,
+    ExternalQueryMsg, InstantiateMsg, MigrateMsg, PalomaMsg, PusdManagerInfo,
+    PusdManagerWithdrawal, QueryMsg, RateLimitResponse, SudoMsg, SwapOperation,
+};
+use crate::state::{
+    AllowedRecipient, ChainSetting, PendingAllowlistDelay, PendingWithdrawal, PusdWithdrawal,
+    RateLimit, State, UnresolvedWithdrawal, WithdrawalStatus, ALLOWED_RECIPIENTS,
+    ALLOWLIST_DISABLED_FROM, ALLOWLIST_ENABLED, CHAIN_SETTINGS, PENDING_WITHDRAWALS, PUSD_MANAGERS,
+    PUSD_WITHDRAWALS, RATE_LIMITS, STATE, STATE_V0_1, UNRESOLVED_WITHDRAWALS,
+    UNRESOLVED_WITHDRAWAL_COUNT,
 };
-use crate::state::{ChainSetting, State, CHAIN_SETTINGS, STATE};
 
 // version info for migration info
 const CONTRACT_NAME: &str = "crates.io:token-purchaser-manager-cw";
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+const PUSD_WITHDRAW_REPLY_ID: u64 = 1;
+
+// pagination for list queries
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
+
+// same limit as the astroport router
+const MAX_SWAP_OPERATIONS: usize = 50;
+
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> StdResult<Response> {
+pub fn migrate(deps: DepsMut, env: Env, msg: MigrateMsg) -> Result<Response, ContractError> {
+    // deployments before 0.2.0 never recorded their version at instantiation
+    let stored = CONTRACT.may_load(deps.storage)?.unwrap_or(ContractVersion {
+        contract: CONTRACT_NAME.to_string(),
+        version: "0.1.0".to_string(),
+    });
+    if stored.contract != CONTRACT_NAME {
+        return Err(ContractError::WrongContract {
+            expected: CONTRACT_NAME.to_string(),
+            actual: stored.contract,
+        });
+    }
+    let stored_version: Version = stored.version.parse()?;
+    let version: Version = CONTRACT_VERSION.parse()?;
+    if stored_version > version {
+        return Err(ContractError::CannotDowngrade {
+            stored: stored.version,
+            version: CONTRACT_VERSION.to_string(),
+        });
+    }
+    if stored_version < Version::new(0, 2, 0) {
+        migrate_state_to_v0_2(deps.storage)?;
+    }
+    let mut state = STATE.load(deps.storage)?;
+    if let Some(retry_delay) = msg.retry_delay {
+        state.retry_delay = retry_delay;
+    }
+    if let Some(allowlist_delay) = msg.allowlist_delay {
+        state.allowlist_delay = allowlist_delay;
+    }
+    if let Some(pusd_manager) = msg.pusd_manager {
+        let pusd_manager = deps.api.addr_validate(&pusd_manager)?;
+        let denom = msg
+            .pusd_denom
+            .unwrap_or_else(|| "factory/".to_string() + pusd_manager.as_str() + "/upusd");
+        PUSD_MANAGERS.save(deps.storage, &pusd_manager, &denom)?;
+        state.pusd_manager = Some(pusd_manager);
+    }
+    STATE.save(deps.storage, &state)?;
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
-    Ok(Response::default())
+    Ok(Response::new().add_attributes(
+        ActionEvent::new("migrate", &env.contract.address)
+            .attribute("from_version", stored.version)
+            .attribute("to_version", CONTRACT_VERSION),
+    ))
+}
+
+/// Rewrites the 0.1.0 `State` in the 0.2.0 layout, starting the fields added since from
+/// their defaults: no allowlist delay, no default pusd_manager and not paused.
+fn migrate_state_to_v0_2(storage: &mut dyn Storage) -> StdResult<()> {
+    let legacy = STATE_V0_1.load(storage)?;
+    let state = State {
+        owners: legacy.owners,
+        retry_delay: legacy.retry_delay,
+        allowlist_delay: 0,
+        pusd_manager: None,
+        paused: false,
+        pending_allowlist_delay: None,
+    };
+    STATE.save(storage, &state)
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
     deps: DepsMut,
     _env: Env,
-    _info: MessageInfo,
+    info: MessageInfo,
     msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
     let state = State {
@@ -40,18 +117,26 @@ pub fn instantiate(
             .map(|x| deps.api.addr_validate(x).unwrap())
             .collect(),
         retry_delay: msg.retry_delay,
+        allowlist_delay: 0,
+        pusd_manager: None,
+        paused: false,
+        pending_allowlist_delay: None,
     };
     STATE.save(deps.storage, &state)?;
-    Ok(Response::new().add_attribute("action", "instantiate"))
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+    Ok(Response::new().add_attributes(ActionEvent::new("instantiate", &info.sender)))
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn execute(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     info: MessageInfo,
     msg: ExecuteMsg,
 ) -> Result<Response<PalomaMsg>, ContractError> {
+    if STATE.load(deps.storage)?.paused {
+        return Err(ContractError::Paused {});
+    }
     match msg {
         ExecuteMsg::DeployPalomaErc20 {
             chain_id,
@@ -62,10 +147,7 @@ pub fn execute(
             blueprint,
         } => {
             let state = STATE.load(deps.storage)?;
-            assert!(
-                state.owners.iter().any(|x| x == info.sender),
-                "Unauthorized"
-            );
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
             #[allow(deprecated)]
             let contract: Contract = Contract {
                 constructor: None,
@@ -118,12 +200,14 @@ pub fn execute(
                 Token::Address(Address::from_str(blueprint.as_str()).unwrap()),
             ];
 
+            let job_id = CHAIN_SETTINGS
+                .load(deps.storage, chain_id.clone())?
+                .compass_job_id;
+
             Ok(Response::new()
                 .add_message(CosmosMsg::Custom(PalomaMsg::SchedulerMsg {
                     execute_job: ExecuteJob {
-                        job_id: CHAIN_SETTINGS
-                            .load(deps.storage, chain_id.clone())?
-                            .compass_job_id,
+                        job_id: job_id.clone(),
                         payload: Binary::new(
                             contract
                                 .function("deploy_erc20")
@@ -133,7 +217,11 @@ pub fn execute(
                         ),
                     },
                 }))
-                .add_attribute("action", "deploy_paloma_erc20"))
+                .add_attributes(
+                    ActionEvent::new("deploy_paloma_erc20", &info.sender)
+                        .chain_id(chain_id)
+                        .job_id(job_id),
+                ))
         }
         ExecuteMsg::Exchange {
             dex_router,
@@ -144,10 +232,14 @@ pub fn execute(
             funds,
         } => {
             let state = STATE.load(deps.storage)?;
-            assert!(
-                state.owners.iter().any(|x| x == info.sender),
-                "Unauthorized"
-            );
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            validate_route(&operations, &funds)?;
+            let mut event = ActionEvent::new("exchange", &info.sender)
+                .amount(funds[0].to_string())
+                .attribute("dex_router", &dex_router);
+            if let Some(to) = &to {
+                event = event.recipient(to);
+            }
             Ok(Response::new()
                 .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
                     contract_addr: dex_router.to_string(),
@@ -159,7 +251,7 @@ pub fn execute(
                     })?,
                     funds,
                 }))
-                .add_attribute("action", "exchange"))
+                .add_attributes(event))
         }
         ExecuteMsg::SendToken {
             chain_id,
@@ -169,10 +261,9 @@ pub fn execute(
             nonce,
         } => {
             let state = STATE.load(deps.storage)?;
-            assert!(
-                state.owners.iter().any(|x| x == info.sender),
-                "Unauthorized"
-            );
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            assert_allowed_recipient(deps.storage, &env, &chain_id, &to)?;
+            consume_rate_limit(deps.storage, &env, &chain_id, &token, amount)?;
             #[allow(deprecated)]
             let contract: Contract = Contract {
                 constructor: None,
@@ -219,12 +310,14 @@ pub fn execute(
                 Token::Uint(Uint::from_big_endian(&nonce.to_be_bytes())),
             ];
 
+            let job_id = CHAIN_SETTINGS
+                .load(deps.storage, chain_id.clone())?
+                .main_job_id;
+
             Ok(Response::new()
                 .add_message(CosmosMsg::Custom(PalomaMsg::SchedulerMsg {
                     execute_job: ExecuteJob {
-                        job_id: CHAIN_SETTINGS
-                            .load(deps.storage, chain_id.clone())?
-                            .main_job_id,
+                        job_id: job_id.clone(),
                         payload: Binary::new(
                             contract
                                 .function("send_token")
@@ -234,7 +327,15 @@ pub fn execute(
                         ),
                     },
                 }))
-                .add_attribute("action", "send_token"))
+                .add_attributes(
+                    ActionEvent::new("send_token", &info.sender)
+                        .chain_id(chain_id)
+                        .job_id(job_id)
+                        .amount(format!("{amount}{token}"))
+                        .recipient(to)
+                        .nonce(nonce)
+                        .attribute("token", token),
+                ))
         }
         ExecuteMsg::WithdrawPusd {
             pusd_manager,
@@ -243,24 +344,39 @@ pub fn execute(
             amount,
         } => {
             let state = STATE.load(deps.storage)?;
-            assert!(
-                state.owners.iter().any(|x| x == info.sender),
-                "Unauthorized"
-            );
-            let pusd_denom: String = "factory/".to_string() + pusd_manager.as_str() + "/upusd";
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let (pusd_manager, pusd_denom) = resolve_pusd_manager(deps.storage, pusd_manager)?;
+            assert_allowed_recipient(deps.storage, &env, &chain_id, &recipient)?;
+            consume_rate_limit(deps.storage, &env, &chain_id, &pusd_denom, amount)?;
+            let withdraw = CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: pusd_manager.to_string(),
+                msg: to_json_binary(&ExternalExecuteMsg::Withdraw {
+                    chain_id: chain_id.clone(),
+                    recipient: recipient.clone(),
+                })?,
+                funds: vec![Coin {
+                    denom: pusd_denom.clone(),
+                    amount,
+                }],
+            });
+            let event = ActionEvent::new("withdraw_pusd", &info.sender)
+                .chain_id(&chain_id)
+                .amount(Coin::new(amount, pusd_denom))
+                .recipient(&recipient)
+                .attribute("pusd_manager", &pusd_manager);
+            // the reply records the withdrawal under the nonce the pusd_manager assigns
+            PENDING_WITHDRAWALS.push_back(
+                deps.storage,
+                &PendingWithdrawal {
+                    pusd_manager,
+                    chain_id,
+                    recipient,
+                    amount,
+                },
+            )?;
             Ok(Response::new()
-                .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
-                    contract_addr: pusd_manager.to_string(),
-                    msg: to_json_binary(&ExternalExecuteMsg::Withdraw {
-                        chain_id,
-                        recipient,
-                    })?,
-                    funds: vec![Coin {
-                        denom: pusd_denom,
-                        amount,
-                    }],
-                }))
-                .add_attribute("action", "withdraw_pusd"))
+                .add_submessage(SubMsg::reply_on_success(withdraw, PUSD_WITHDRAW_REPLY_ID))
+                .add_attributes(event))
         }
 
         ExecuteMsg::ReWithdrawPusd {
@@ -268,17 +384,25 @@ pub fn execute(
             nonce,
         } => {
             let state = STATE.load(deps.storage)?;
-            assert!(
-                state.owners.iter().any(|x| x == info.sender),
-                "Unauthorized"
-            );
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let (pusd_manager, _) = resolve_pusd_manager(deps.storage, pusd_manager)?;
+            let withdrawal = PUSD_WITHDRAWALS
+                .may_load(deps.storage, (&pusd_manager, nonce))?
+                .ok_or(ContractError::UnknownWithdrawal { nonce })?;
+            if withdrawal.status != WithdrawalStatus::Pending {
+                return Err(ContractError::WithdrawalNotPending { nonce });
+            }
             Ok(Response::new()
                 .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
                     contract_addr: pusd_manager.to_string(),
                     msg: to_json_binary(&ExternalExecuteMsg::ReWithdraw { nonce })?,
                     funds: vec![],
                 }))
-                .add_attribute("action", "re_withdraw_pusd"))
+                .add_attributes(
+                    ActionEvent::new("re_withdraw_pusd", &info.sender)
+                        .nonce(nonce)
+                        .attribute("pusd_manager", pusd_manager),
+                ))
         }
 
         ExecuteMsg::CancelWithdrawPusd {
@@ -286,17 +410,110 @@ pub fn execute(
             nonce,
         } => {
             let state = STATE.load(deps.storage)?;
-            assert!(
-                state.owners.iter().any(|x| x == info.sender),
-                "Unauthorized"
-            );
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let (pusd_manager, _) = resolve_pusd_manager(deps.storage, pusd_manager)?;
+            let mut withdrawal = PUSD_WITHDRAWALS
+                .may_load(deps.storage, (&pusd_manager, nonce))?
+                .ok_or(ContractError::UnknownWithdrawal { nonce })?;
+            if withdrawal.status != WithdrawalStatus::Pending {
+                return Err(ContractError::WithdrawalNotPending { nonce });
+            }
+            withdrawal.status = WithdrawalStatus::Cancelled;
+            PUSD_WITHDRAWALS.save(deps.storage, (&pusd_manager, nonce), &withdrawal)?;
             Ok(Response::new()
                 .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
                     contract_addr: pusd_manager.to_string(),
                     msg: to_json_binary(&ExternalExecuteMsg::CancelWithdraw { nonce })?,
                     funds: vec![],
                 }))
-                .add_attribute("action", "cancel_withdraw_pusd"))
+                .add_attributes(
+                    ActionEvent::new("cancel_withdraw_pusd", &info.sender)
+                        .nonce(nonce)
+                        .attribute("pusd_manager", pusd_manager),
+                ))
+        }
+
+        ExecuteMsg::CompleteWithdrawPusd {
+            pusd_manager,
+            nonce,
+        } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let (pusd_manager, _) = resolve_pusd_manager(deps.storage, pusd_manager)?;
+            let mut withdrawal = PUSD_WITHDRAWALS
+                .may_load(deps.storage, (&pusd_manager, nonce))?
+                .ok_or(ContractError::UnknownWithdrawal { nonce })?;
+            if withdrawal.status != WithdrawalStatus::Pending {
+                return Err(ContractError::WithdrawalNotPending { nonce });
+            }
+            withdrawal.status = WithdrawalStatus::Completed;
+            PUSD_WITHDRAWALS.save(deps.storage, (&pusd_manager, nonce), &withdrawal)?;
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("complete_withdraw_pusd", &info.sender)
+                    .nonce(nonce)
+                    .attribute("pusd_manager", pusd_manager),
+            ))
+        }
+
+        ExecuteMsg::RegisterWithdrawPusd {
+            pusd_manager,
+            nonce,
+            chain_id,
+            recipient,
+            amount,
+        } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let (pusd_manager, pusd_denom) = resolve_pusd_manager(deps.storage, pusd_manager)?;
+            record_pusd_withdrawal(
+                deps.storage,
+                PusdWithdrawal {
+                    pusd_manager: pusd_manager.clone(),
+                    nonce,
+                    chain_id: chain_id.clone(),
+                    recipient: recipient.clone(),
+                    amount,
+                    status: WithdrawalStatus::Pending,
+                },
+            )?;
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("register_withdraw_pusd", &info.sender)
+                    .chain_id(chain_id)
+                    .amount(Coin::new(amount, pusd_denom))
+                    .recipient(recipient)
+                    .nonce(nonce)
+                    .attribute("pusd_manager", pusd_manager),
+            ))
+        }
+
+        ExecuteMsg::ResolveWithdrawPusd { id, nonce } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let unresolved = UNRESOLVED_WITHDRAWALS
+                .may_load(deps.storage, id)?
+                .ok_or(ContractError::UnknownUnresolvedWithdrawal { id })?;
+            record_pusd_withdrawal(
+                deps.storage,
+                PusdWithdrawal {
+                    pusd_manager: unresolved.pusd_manager.clone(),
+                    nonce,
+                    chain_id: unresolved.chain_id.clone(),
+                    recipient: unresolved.recipient.clone(),
+                    amount: unresolved.amount,
+                    status: WithdrawalStatus::Pending,
+                },
+            )?;
+            UNRESOLVED_WITHDRAWALS.remove(deps.storage, id);
+            let pusd_denom = PUSD_MANAGERS.load(deps.storage, &unresolved.pusd_manager)?;
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("resolve_withdraw_pusd", &info.sender)
+                    .chain_id(unresolved.chain_id)
+                    .amount(Coin::new(unresolved.amount, pusd_denom))
+                    .recipient(unresolved.recipient)
+                    .nonce(nonce)
+                    .attribute("unresolved_id", id)
+                    .attribute("pusd_manager", unresolved.pusd_manager),
+            ))
         }
         ExecuteMsg::SetChainSetting {
             chain_id,
@@ -304,10 +521,7 @@ pub fn execute(
             main_job_id,
         } => {
             let state = STATE.load(deps.storage)?;
-            assert!(
-                state.owners.iter().any(|x| x == info.sender),
-                "Unauthorized"
-            );
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
             CHAIN_SETTINGS.save(
                 deps.storage,
                 chain_id.clone(),
@@ -317,15 +531,17 @@ pub fn execute(
                 },
             )?;
 
-            Ok(Response::new().add_attribute("action", "set_chain_setting"))
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("set_chain_setting", &info.sender)
+                    .chain_id(chain_id)
+                    .job_id(main_job_id)
+                    .attribute("compass_job_id", compass_job_id),
+            ))
         }
         ExecuteMsg::SetPaloma { chain_id } => {
             // ACTION: Implement SetPaloma
             let state = STATE.load(deps.storage)?;
-            assert!(
-                state.owners.iter().any(|x| x == info.sender),
-                "Unauthorized"
-            );
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
 
             #[allow(deprecated)]
             let contract: Contract = Contract {
@@ -345,12 +561,13 @@ pub fn execute(
                 receive: false,
                 fallback: false,
             };
+            let job_id = CHAIN_SETTINGS
+                .load(deps.storage, chain_id.clone())?
+                .main_job_id;
             Ok(Response::new()
                 .add_message(CosmosMsg::Custom(PalomaMsg::SchedulerMsg {
                     execute_job: ExecuteJob {
-                        job_id: CHAIN_SETTINGS
-                            .load(deps.storage, chain_id.clone())?
-                            .main_job_id,
+                        job_id: job_id.clone(),
                         payload: Binary::new(
                             contract
                                 .function("set_paloma")
@@ -360,17 +577,18 @@ pub fn execute(
                         ),
                     },
                 }))
-                .add_attribute("action", "set_paloma"))
+                .add_attributes(
+                    ActionEvent::new("set_paloma", &info.sender)
+                        .chain_id(chain_id)
+                        .job_id(job_id),
+                ))
         }
         ExecuteMsg::UpdateCompass {
             chain_id,
             new_compass,
         } => {
             let state = STATE.load(deps.storage)?;
-            assert!(
-                state.owners.iter().any(|x| x == info.sender),
-                "Unauthorized"
-            );
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
 
             #[allow(deprecated)]
             let contract: Contract = Contract {
@@ -397,12 +615,13 @@ pub fn execute(
             let tokens = &[Token::Address(
                 Address::from_str(new_compass.as_str()).unwrap(),
             )];
+            let job_id = CHAIN_SETTINGS
+                .load(deps.storage, chain_id.clone())?
+                .main_job_id;
             Ok(Response::new()
                 .add_message(CosmosMsg::Custom(PalomaMsg::SchedulerMsg {
                     execute_job: ExecuteJob {
-                        job_id: CHAIN_SETTINGS
-                            .load(deps.storage, chain_id.clone())?
-                            .main_job_id,
+                        job_id: job_id.clone(),
                         payload: Binary::new(
                             contract
                                 .function("update_compass")
@@ -412,21 +631,19 @@ pub fn execute(
                         ),
                     },
                 }))
-                .add_attributes(vec![
-                    ("action", "update_compass"),
-                    ("chain_id", &chain_id),
-                    ("new_compass", new_compass.as_str()),
-                ]))
+                .add_attributes(
+                    ActionEvent::new("update_compass", &info.sender)
+                        .chain_id(chain_id)
+                        .job_id(job_id)
+                        .attribute("new_compass", new_compass),
+                ))
         }
         ExecuteMsg::UpdateRefundWallet {
             chain_id,
             new_refund_wallet,
         } => {
             let state = STATE.load(deps.storage)?;
-            assert!(
-                state.owners.iter().any(|x| x == info.sender),
-                "Unauthorized"
-            );
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
             let update_refund_wallet_address: Address =
                 Address::from_str(new_refund_wallet.as_str()).unwrap();
             #[allow(deprecated)]
@@ -451,12 +668,13 @@ pub fn execute(
                 receive: false,
                 fallback: false,
             };
+            let job_id = CHAIN_SETTINGS
+                .load(deps.storage, chain_id.clone())?
+                .main_job_id;
             Ok(Response::new()
                 .add_message(CosmosMsg::Custom(PalomaMsg::SchedulerMsg {
                     execute_job: ExecuteJob {
-                        job_id: CHAIN_SETTINGS
-                            .load(deps.storage, chain_id.clone())?
-                            .main_job_id,
+                        job_id: job_id.clone(),
                         payload: Binary::new(
                             contract
                                 .function("update_refund_wallet")
@@ -466,17 +684,19 @@ pub fn execute(
                         ),
                     },
                 }))
-                .add_attribute("action", "update_refund_wallet"))
+                .add_attributes(
+                    ActionEvent::new("update_refund_wallet", &info.sender)
+                        .chain_id(chain_id)
+                        .job_id(job_id)
+                        .attribute("new_refund_wallet", new_refund_wallet),
+                ))
         }
         ExecuteMsg::UpdateGasFee {
             chain_id,
             new_gas_fee,
         } => {
             let state = STATE.load(deps.storage)?;
-            assert!(
-                state.owners.iter().any(|x| x == info.sender),
-                "Unauthorized"
-            );
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
             #[allow(deprecated)]
             let contract: Contract = Contract {
                 constructor: None,
@@ -499,12 +719,13 @@ pub fn execute(
                 receive: false,
                 fallback: false,
             };
+            let job_id = CHAIN_SETTINGS
+                .load(deps.storage, chain_id.clone())?
+                .main_job_id;
             Ok(Response::new()
                 .add_message(CosmosMsg::Custom(PalomaMsg::SchedulerMsg {
                     execute_job: ExecuteJob {
-                        job_id: CHAIN_SETTINGS
-                            .load(deps.storage, chain_id.clone())?
-                            .main_job_id,
+                        job_id: job_id.clone(),
                         payload: Binary::new(
                             contract
                                 .function("update_gas_fee")
@@ -516,17 +737,19 @@ pub fn execute(
                         ),
                     },
                 }))
-                .add_attribute("action", "update_gas_fee"))
+                .add_attributes(
+                    ActionEvent::new("update_gas_fee", &info.sender)
+                        .chain_id(chain_id)
+                        .job_id(job_id)
+                        .attribute("new_gas_fee", new_gas_fee),
+                ))
         }
         ExecuteMsg::UpdateServiceFeeCollector {
             chain_id,
             new_service_fee_collector,
         } => {
             let state = STATE.load(deps.storage)?;
-            assert!(
-                state.owners.iter().any(|x| x == info.sender),
-                "Unauthorized"
-            );
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
             let update_service_fee_collector_address: Address =
                 Address::from_str(new_service_fee_collector.as_str()).unwrap();
             #[allow(deprecated)]
@@ -551,12 +774,13 @@ pub fn execute(
                 receive: false,
                 fallback: false,
             };
+            let job_id = CHAIN_SETTINGS
+                .load(deps.storage, chain_id.clone())?
+                .main_job_id;
             Ok(Response::new()
                 .add_message(CosmosMsg::Custom(PalomaMsg::SchedulerMsg {
                     execute_job: ExecuteJob {
-                        job_id: CHAIN_SETTINGS
-                            .load(deps.storage, chain_id.clone())?
-                            .main_job_id,
+                        job_id: job_id.clone(),
                         payload: Binary::new(
                             contract
                                 .function("update_service_fee_collector")
@@ -568,17 +792,19 @@ pub fn execute(
                         ),
                     },
                 }))
-                .add_attribute("action", "update_service_fee_collector"))
+                .add_attributes(
+                    ActionEvent::new("update_service_fee_collector", &info.sender)
+                        .chain_id(chain_id)
+                        .job_id(job_id)
+                        .attribute("new_service_fee_collector", new_service_fee_collector),
+                ))
         }
         ExecuteMsg::UpdateServiceFee {
             chain_id,
             new_service_fee,
         } => {
             let state = STATE.load(deps.storage)?;
-            assert!(
-                state.owners.iter().any(|x| x == info.sender),
-                "Unauthorized"
-            );
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
             #[allow(deprecated)]
             let contract: Contract = Contract {
                 constructor: None,
@@ -601,12 +827,13 @@ pub fn execute(
                 receive: false,
                 fallback: false,
             };
+            let job_id = CHAIN_SETTINGS
+                .load(deps.storage, chain_id.clone())?
+                .main_job_id;
             Ok(Response::new()
                 .add_message(CosmosMsg::Custom(PalomaMsg::SchedulerMsg {
                     execute_job: ExecuteJob {
-                        job_id: CHAIN_SETTINGS
-                            .load(deps.storage, chain_id.clone())?
-                            .main_job_id,
+                        job_id: job_id.clone(),
                         payload: Binary::new(
                             contract
                                 .function("update_service_fee")
@@ -618,62 +845,1189 @@ pub fn execute(
                         ),
                     },
                 }))
-                .add_attribute("action", "update_service_fee"))
+                .add_attributes(
+                    ActionEvent::new("update_service_fee", &info.sender)
+                        .chain_id(chain_id)
+                        .job_id(job_id)
+                        .attribute("new_service_fee", new_service_fee),
+                ))
         }
-        ExecuteMsg::UpdateConfig { retry_delay } => {
+        ExecuteMsg::SetRateLimit {
+            chain_id,
+            denom,
+            max_amount,
+            window,
+        } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            save_rate_limit(deps.storage, &chain_id, &denom, max_amount, window, false)?;
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("set_rate_limit", &info.sender)
+                    .chain_id(chain_id)
+                    .attribute("denom", denom)
+                    .attribute("max_amount", max_amount)
+                    .attribute("window", window),
+            ))
+        }
+        ExecuteMsg::SetPusdManager {
+            pusd_manager,
+            denom,
+            is_default,
+        } => {
+            let mut state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let pusd_manager = deps.api.addr_validate(&pusd_manager)?;
+            let denom =
+                denom.unwrap_or_else(|| "factory/".to_string() + pusd_manager.as_str() + "/upusd");
+            PUSD_MANAGERS.save(deps.storage, &pusd_manager, &denom)?;
+            if is_default {
+                state.pusd_manager = Some(pusd_manager.clone());
+                STATE.save(deps.storage, &state)?;
+            }
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("set_pusd_manager", &info.sender)
+                    .attribute("pusd_manager", pusd_manager)
+                    .attribute("denom", denom)
+                    .attribute("is_default", is_default),
+            ))
+        }
+        ExecuteMsg::RemovePusdManager { pusd_manager } => {
+            let mut state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let pusd_manager = deps.api.addr_validate(&pusd_manager)?;
+            PUSD_MANAGERS.remove(deps.storage, &pusd_manager);
+            if state.pusd_manager.as_ref() == Some(&pusd_manager) {
+                state.pusd_manager = None;
+                STATE.save(deps.storage, &state)?;
+            }
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("remove_pusd_manager", &info.sender)
+                    .attribute("pusd_manager", pusd_manager),
+            ))
+        }
+        ExecuteMsg::SetAllowlistEnabled { chain_id, enabled } => {
+            let mut state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let mut event = ActionEvent::new("set_allowlist_enabled", &info.sender)
+                .chain_id(&chain_id)
+                .attribute("enabled", enabled);
+            let allowlist_delay = current_allowlist_delay(&mut state, &env);
+            STATE.save(deps.storage, &state)?;
+            if enabled {
+                ALLOWLIST_ENABLED.save(deps.storage, &chain_id, &true)?;
+                ALLOWLIST_DISABLED_FROM.remove(deps.storage, &chain_id);
+            } else if allowlist_delay == 0 {
+                ALLOWLIST_ENABLED.remove(deps.storage, &chain_id);
+                ALLOWLIST_DISABLED_FROM.remove(deps.storage, &chain_id);
+            } else if ALLOWLIST_ENABLED
+                .may_load(deps.storage, &chain_id)?
+                .unwrap_or_default()
+            {
+                // lifting the allowlist waits out the same delay as adding a recipient
+                let disabled_from = env.block.time.seconds() + allowlist_delay;
+                ALLOWLIST_DISABLED_FROM.save(deps.storage, &chain_id, &disabled_from)?;
+                event = event.attribute("disabled_from", disabled_from);
+            }
+            Ok(Response::new().add_attributes(event))
+        }
+        ExecuteMsg::AddAllowedRecipient {
+            chain_id,
+            recipient,
+        } => {
             let mut state = STATE.load(deps.storage)?;
-            assert!(
-                state.owners.iter().any(|x| x == info.sender),
-                "Unauthorized"
-            );
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            let active_from = env.block.time.seconds() + current_allowlist_delay(&mut state, &env);
+            STATE.save(deps.storage, &state)?;
+            ALLOWED_RECIPIENTS.save(
+                deps.storage,
+                (&chain_id, &recipient.to_lowercase()),
+                &AllowedRecipient {
+                    added_at_height: env.block.height,
+                    active_from,
+                },
+            )?;
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("add_allowed_recipient", &info.sender)
+                    .chain_id(chain_id)
+                    .recipient(recipient)
+                    .attribute("active_from", active_from),
+            ))
+        }
+        ExecuteMsg::RemoveAllowedRecipient {
+            chain_id,
+            recipient,
+        } => {
+            let state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
+            ALLOWED_RECIPIENTS.remove(deps.storage, (&chain_id, &recipient.to_lowercase()));
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("remove_allowed_recipient", &info.sender)
+                    .chain_id(chain_id)
+                    .recipient(recipient),
+            ))
+        }
+        ExecuteMsg::UpdateConfig {
+            retry_delay,
+            allowlist_delay,
+        } => {
+            let mut state = STATE.load(deps.storage)?;
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
             if let Some(retry_delay) = retry_delay {
                 state.retry_delay = retry_delay;
             }
+            if let Some(allowlist_delay) = allowlist_delay {
+                update_allowlist_delay(&mut state, &env, allowlist_delay);
+            }
             STATE.save(deps.storage, &state)?;
-            Ok(Response::new().add_attribute("action", "update_config"))
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("update_config", &info.sender)
+                    .attribute("retry_delay", state.retry_delay)
+                    .attribute("allowlist_delay", state.allowlist_delay),
+            ))
         }
         ExecuteMsg::AddOwner { owners } => {
             let mut state = STATE.load(deps.storage)?;
-            assert!(
-                state.owners.iter().any(|x| x == info.sender),
-                "Unauthorized"
-            );
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
             for owner in owners.iter() {
                 let owner = deps.api.addr_validate(owner)?;
-                if !state.owners.iter().any(|x| x == owner) {
+                if !state.owners.contains(&owner) {
                     state.owners.push(owner);
                 }
             }
             STATE.save(deps.storage, &state)?;
-            Ok(Response::new().add_attribute("action", "update_config"))
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("add_owner", &info.sender).attribute("owners", owners.join(",")),
+            ))
         }
         ExecuteMsg::RemoveOwner { owner } => {
             let mut state = STATE.load(deps.storage)?;
-            assert!(
-                state.owners.iter().any(|x| x == info.sender),
-                "Unauthorized"
-            );
+            assert!(state.owners.contains(&info.sender), "Unauthorized");
             let owner = deps.api.addr_validate(&owner)?;
-            assert!(
-                state.owners.iter().any(|x| x == owner),
-                "Owner does not exist"
-            );
+            assert!(state.owners.contains(&owner), "Owner does not exist");
             state.owners.retain(|x| x != owner);
             STATE.save(deps.storage, &state)?;
-            Ok(Response::new().add_attribute("action", "update_config"))
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("remove_owner", &info.sender).attribute("owner", owner),
+            ))
+        }
+    }
+}
+
+/// Checks that `operations` form a connected route and that `funds` is exactly
+/// the offer asset of the first hop.
+fn validate_route(operations: &[SwapOperation], funds: &[Coin]) -> Result<(), ContractError> {
+    let first = operations.first().ok_or(ContractError::EmptyRoute {})?;
+    if operations.len() > MAX_SWAP_OPERATIONS {
+        return Err(ContractError::TooManyHops {
+            hops: operations.len(),
+            max: MAX_SWAP_OPERATIONS,
+        });
+    }
+    for (index, hops) in operations.windows(2).enumerate() {
+        if hops[0].ask_asset_info() != hops[1].offer_asset_info() {
+            return Err(ContractError::BrokenRoute {
+                index: index + 1,
+                offer: hops[1].offer_asset_info().to_string(),
+                ask: hops[0].ask_asset_info().to_string(),
+            });
+        }
+    }
+    match first.offer_asset_info() {
+        AssetInfo::NativeToken { denom }
+            if funds.len() == 1 && funds[0].denom == *denom && !funds[0].amount.is_zero() =>
+        {
+            Ok(())
+        }
+        offer_asset_info => Err(ContractError::FundsMismatch {
+            expected: offer_asset_info.to_string(),
+        }),
+    }
+}
+
+/// Finds the withdrawal nonce among the events `pusd_manager` emitted in a `withdraw` reply.
+fn parse_withdrawal_nonce(events: &[Event], pusd_manager: &Addr) -> Option<u64> {
+    events
+        .iter()
+        .filter(|event| {
+            event.ty.starts_with("wasm")
+                && event.attributes.iter().any(|attribute| {
+                    attribute.key == "_contract_address" && attribute.value == pusd_manager.as_str()
+                })
+        })
+        .flat_map(|event| event.attributes.iter())
+        .find(|attribute| attribute.key == "nonce")
+        .and_then(|attribute| attribute.value.parse().ok())
+}
+
+/// Records a pending withdrawal under its nonce, refusing to overwrite a recorded one.
+fn record_pusd_withdrawal(
+    storage: &mut dyn Storage,
+    withdrawal: PusdWithdrawal,
+) -> Result<(), ContractError> {
+    let key = (&withdrawal.pusd_manager, withdrawal.nonce);
+    if PUSD_WITHDRAWALS.has(storage, key) {
+        return Err(ContractError::WithdrawalAlreadyRecorded {
+            nonce: withdrawal.nonce,
+        });
+    }
+    PUSD_WITHDRAWALS.save(storage, key, &withdrawal)?;
+    Ok(())
+}
+
+/// Resolves the pusd_manager of a call to the default one when none is given. An explicit
+/// pusd_manager must be registered. Returns the pusd_manager with its PUSD denom.
+fn resolve_pusd_manager(
+    storage: &dyn Storage,
+    pusd_manager: Option<Addr>,
+) -> Result<(Addr, String), ContractError> {
+    let pusd_manager = match pusd_manager {
+        Some(pusd_manager) => pusd_manager,
+        None => STATE
+            .load(storage)?
+            .pusd_manager
+            .ok_or(ContractError::NoDefaultPusdManager {})?,
+    };
+    let denom = PUSD_MANAGERS.may_load(storage, &pusd_manager)?.ok_or(
+        ContractError::UnregisteredPusdManager {
+            pusd_manager: pusd_manager.to_string(),
+        },
+    )?;
+    Ok((pusd_manager, denom))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn sudo(deps: DepsMut, env: Env, msg: SudoMsg) -> Result<Response<PalomaMsg>, ContractError> {
+    match msg {
+        SudoMsg::SetOwners { owners } => {
+            let mut state = STATE.load(deps.storage)?;
+            state.owners = owners
+                .iter()
+                .map(|owner| deps.api.addr_validate(owner))
+                .collect::<StdResult<Vec<Addr>>>()?;
+            STATE.save(deps.storage, &state)?;
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("sudo_set_owners", &env.contract.address)
+                    .attribute("owners", owners.join(",")),
+            ))
+        }
+        SudoMsg::Pause {} => {
+            let mut state = STATE.load(deps.storage)?;
+            state.paused = true;
+            STATE.save(deps.storage, &state)?;
+            Ok(Response::new()
+                .add_attributes(ActionEvent::new("sudo_pause", &env.contract.address)))
+        }
+        SudoMsg::Unpause {} => {
+            let mut state = STATE.load(deps.storage)?;
+            state.paused = false;
+            STATE.save(deps.storage, &state)?;
+            Ok(Response::new()
+                .add_attributes(ActionEvent::new("sudo_unpause", &env.contract.address)))
+        }
+        SudoMsg::CancelWithdrawPusd {
+            pusd_manager,
+            nonce,
+        } => {
+            // withdrawals the contract did not record can be cancelled too
+            if let Some(mut withdrawal) =
+                PUSD_WITHDRAWALS.may_load(deps.storage, (&pusd_manager, nonce))?
+            {
+                withdrawal.status = WithdrawalStatus::Cancelled;
+                PUSD_WITHDRAWALS.save(deps.storage, (&pusd_manager, nonce), &withdrawal)?;
+            }
+            Ok(Response::new()
+                .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: pusd_manager.to_string(),
+                    msg: to_json_binary(&ExternalExecuteMsg::CancelWithdraw { nonce })?,
+                    funds: vec![],
+                }))
+                .add_attributes(
+                    ActionEvent::new("sudo_cancel_withdraw_pusd", &env.contract.address)
+                        .nonce(nonce)
+                        .attribute("pusd_manager", pusd_manager),
+                ))
+        }
+        SudoMsg::SetRateLimit {
+            chain_id,
+            denom,
+            max_amount,
+            window,
+        } => {
+            save_rate_limit(deps.storage, &chain_id, &denom, max_amount, window, true)?;
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("sudo_set_rate_limit", &env.contract.address)
+                    .chain_id(chain_id)
+                    .attribute("denom", denom)
+                    .attribute("max_amount", max_amount)
+                    .attribute("window", window),
+            ))
+        }
+        SudoMsg::RemoveRateLimit { chain_id, denom } => {
+            RATE_LIMITS.remove(deps.storage, (&chain_id, &rate_limit_denom(&denom)));
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("sudo_remove_rate_limit", &env.contract.address)
+                    .chain_id(chain_id)
+                    .attribute("denom", denom),
+            ))
         }
     }
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+pub fn reply(deps: DepsMut, env: Env, msg: Reply) -> Result<Response<PalomaMsg>, ContractError> {
+    match msg.id {
+        PUSD_WITHDRAW_REPLY_ID => {
+            let pending = PENDING_WITHDRAWALS
+                .pop_front(deps.storage)?
+                .ok_or_else(|| StdError::not_found("pending withdrawal"))?;
+            let response = msg.result.into_result().map_err(StdError::generic_err)?;
+            let pusd_denom = PUSD_MANAGERS.load(deps.storage, &pending.pusd_manager)?;
+            let Some(nonce) = parse_withdrawal_nonce(&response.events, &pending.pusd_manager)
+            else {
+                // the withdrawal went out, so keep it for an owner to resolve rather than
+                // reverting it
+                let id = UNRESOLVED_WITHDRAWAL_COUNT
+                    .may_load(deps.storage)?
+                    .unwrap_or(0)
+                    + 1;
+                UNRESOLVED_WITHDRAWAL_COUNT.save(deps.storage, &id)?;
+                UNRESOLVED_WITHDRAWALS.save(
+                    deps.storage,
+                    id,
+                    &UnresolvedWithdrawal {
+                        id,
+                        pusd_manager: pending.pusd_manager.clone(),
+                        chain_id: pending.chain_id.clone(),
+                        recipient: pending.recipient.clone(),
+                        amount: pending.amount,
+                    },
+                )?;
+                return Ok(Response::new().add_attributes(
+                    ActionEvent::new("withdraw_pusd_unresolved", &env.contract.address)
+                        .chain_id(pending.chain_id)
+                        .amount(Coin::new(pending.amount, pusd_denom))
+                        .recipient(pending.recipient)
+                        .attribute("unresolved_id", id)
+                        .attribute("pusd_manager", pending.pusd_manager),
+                ));
+            };
+            PUSD_WITHDRAWALS.save(
+                deps.storage,
+                (&pending.pusd_manager, nonce),
+                &PusdWithdrawal {
+                    pusd_manager: pending.pusd_manager.clone(),
+                    nonce,
+                    chain_id: pending.chain_id.clone(),
+                    recipient: pending.recipient.clone(),
+                    amount: pending.amount,
+                    status: WithdrawalStatus::Pending,
+                },
+            )?;
+            Ok(Response::new().add_attributes(
+                ActionEvent::new("withdraw_pusd_recorded", &env.contract.address)
+                    .chain_id(pending.chain_id)
+                    .amount(Coin::new(pending.amount, pusd_denom))
+                    .recipient(pending.recipient)
+                    .nonce(nonce)
+                    .attribute("pusd_manager", pending.pusd_manager),
+            ))
+        }
+        id => Err(ContractError::UnknownReplyId { id }),
+    }
+}
+
+/// Rejects `recipient` when the allowlist of `chain_id` is enabled and the recipient is
+/// not listed, was added in the current block or is still within the allowlist delay.
+fn assert_allowed_recipient(
+    storage: &dyn Storage,
+    env: &Env,
+    chain_id: &str,
+    recipient: &str,
+) -> Result<(), ContractError> {
+    if !allowlist_enabled(storage, env, chain_id)? {
+        return Ok(());
+    }
+    match ALLOWED_RECIPIENTS.may_load(storage, (chain_id, &recipient.to_lowercase()))? {
+        Some(allowed)
+            if env.block.height > allowed.added_at_height
+                && env.block.time.seconds() >= allowed.active_from =>
+        {
+            Ok(())
+        }
+        _ => Err(ContractError::RecipientNotAllowed {
+            chain_id: chain_id.to_string(),
+            recipient: recipient.to_string(),
+        }),
+    }
+}
+
+/// Whether the allowlist of `chain_id` is enforced, counting a requested disable only once
+/// it takes effect.
+fn allowlist_enabled(storage: &dyn Storage, env: &Env, chain_id: &str) -> StdResult<bool> {
+    if !ALLOWLIST_ENABLED
+        .may_load(storage, chain_id)?
+        .unwrap_or_default()
+    {
+        return Ok(false);
+    }
+    Ok(ALLOWLIST_DISABLED_FROM
+        .may_load(storage, chain_id)?
+        .is_none_or(|disabled_from| env.block.time.seconds() < disabled_from))
+}
+
+/// Returns the allowlist delay in force, applying a lowered delay once it is due.
+fn current_allowlist_delay(state: &mut State, env: &Env) -> u64 {
+    if let Some(pending) = &state.pending_allowlist_delay {
+        if env.block.time.seconds() >= pending.effective_at {
+            state.allowlist_delay = pending.allowlist_delay;
+            state.pending_allowlist_delay = None;
+        }
+    }
+    state.allowlist_delay
+}
+
+/// Raises the allowlist delay at once. A lower delay only applies after the current delay
+/// has passed, so it cannot shorten the wait of a recipient added in the meantime.
+fn update_allowlist_delay(state: &mut State, env: &Env, allowlist_delay: u64) {
+    let current = current_allowlist_delay(state, env);
+    if allowlist_delay >= current {
+        state.allowlist_delay = allowlist_delay;
+        state.pending_allowlist_delay = None;
+    } else {
+        state.pending_allowlist_delay = Some(PendingAllowlistDelay {
+            allowlist_delay,
+            effective_at: env.block.time.seconds() + current,
+        });
+    }
+}
+
+/// Amount counted against a rate limit at `now`. Usage decays linearly, so the full
+/// `max_amount` is available again one window after the last outflow.
+fn rate_limit_used(rate_limit: &RateLimit, now: u64) -> Uint128 {
+    let elapsed = now
+        .saturating_sub(rate_limit.updated_at)
+        .min(rate_limit.window);
+    rate_limit.used.saturating_sub(
+        rate_limit
+            .max_amount
+            .multiply_ratio(elapsed, rate_limit.window),
+    )
+}
+
+/// Normalises the denom rate limits are keyed by. EVM token addresses are case-insensitive,
+/// so limits must match however a denom is spelled.
+fn rate_limit_denom(denom: &str) -> String {
+    denom.to_lowercase()
+}
+
+/// Saves the rate limit of `denom` to `chain_id`, keeping the usage already counted. Unless
+/// `allow_loosening`, only a new limit or a tighter one is accepted.
+fn save_rate_limit(
+    storage: &mut dyn Storage,
+    chain_id: &str,
+    denom: &str,
+    max_amount: Uint128,
+    window: u64,
+    allow_loosening: bool,
+) -> Result<(), ContractError> {
+    if window == 0 {
+        return Err(ContractError::InvalidRateLimit {});
+    }
+    let denom = &rate_limit_denom(denom);
+    let (used, updated_at) = match RATE_LIMITS.may_load(storage, (chain_id, denom))? {
+        Some(current) => {
+            if !allow_loosening && (max_amount > current.max_amount || window < current.window) {
+                return Err(ContractError::RateLimitLoosened {});
+            }
+            (current.used, current.updated_at)
+        }
+        None => (Uint128::zero(), 0),
+    };
+    RATE_LIMITS.save(
+        storage,
+        (chain_id, denom),
+        &RateLimit {
+            max_amount,
+            window,
+            used,
+            updated_at,
+        },
+    )?;
+    Ok(())
+}
+
+/// Counts an outflow of `amount` of `denom` to `chain_id` against its rate limit, if any.
+fn consume_rate_limit(
+    storage: &mut dyn Storage,
+    env: &Env,
+    chain_id: &str,
+    denom: &str,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    let denom = &rate_limit_denom(denom);
+    let Some(mut rate_limit) = RATE_LIMITS.may_load(storage, (chain_id, denom))? else {
+        return Ok(());
+    };
+    let now = env.block.time.seconds();
+    let used = rate_limit_used(&rate_limit, now);
+    if used + amount > rate_limit.max_amount {
+        return Err(ContractError::RateLimitExceeded {
+            chain_id: chain_id.to_string(),
+            denom: denom.to_string(),
+            remaining: rate_limit.max_amount.saturating_sub(used),
+        });
+    }
+    rate_limit.used = used + amount;
+    rate_limit.updated_at = now;
+    RATE_LIMITS.save(storage, (chain_id, denom), &rate_limit)?;
+    Ok(())
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::GetState {} => to_json_binary(&STATE.load(deps.storage)?),
         QueryMsg::GetChainSetting { chain_id } => {
             to_json_binary(&CHAIN_SETTINGS.load(deps.storage, chain_id)?)
         }
+        QueryMsg::GetOutstandingPusdWithdrawals {
+            pusd_manager,
+            start_after,
+            limit,
+        } => {
+            let pusd_manager = match pusd_manager {
+                Some(pusd_manager) => pusd_manager,
+                None => STATE
+                    .load(deps.storage)?
+                    .pusd_manager
+                    .ok_or_else(|| StdError::not_found("default pusd_manager"))?,
+            };
+            let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+            let withdrawals = PUSD_WITHDRAWALS
+                .prefix(&pusd_manager)
+                .range(
+                    deps.storage,
+                    start_after.map(Bound::exclusive),
+                    None,
+                    Order::Ascending,
+                )
+                .filter(|item| {
+                    item.as_ref().map_or(true, |(_, withdrawal)| {
+                        withdrawal.status == WithdrawalStatus::Pending
+                    })
+                })
+                .take(limit)
+                .map(|item| item.map(|(_, withdrawal)| withdrawal))
+                .collect::<StdResult<Vec<PusdWithdrawal>>>()?;
+            to_json_binary(&withdrawals)
+        }
+        QueryMsg::GetUnresolvedPusdWithdrawals { start_after, limit } => {
+            let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+            let withdrawals = UNRESOLVED_WITHDRAWALS
+                .range(
+                    deps.storage,
+                    start_after.map(Bound::exclusive),
+                    None,
+                    Order::Ascending,
+                )
+                .take(limit)
+                .map(|item| item.map(|(_, withdrawal)| withdrawal))
+                .collect::<StdResult<Vec<UnresolvedWithdrawal>>>()?;
+            to_json_binary(&withdrawals)
+        }
+        QueryMsg::GetPusdWithdrawal {
+            pusd_manager,
+            nonce,
+        } => {
+            let (pusd_manager, _) = resolve_pusd_manager(deps.storage, pusd_manager)
+                .map_err(|err| StdError::generic_err(err.to_string()))?;
+            let withdrawal: PusdManagerWithdrawal = deps
+                .querier
+                .query_wasm_smart(pusd_manager, &ExternalQueryMsg::GetWithdrawal { nonce })?;
+            to_json_binary(&withdrawal)
+        }
+        QueryMsg::GetPusdBalance { pusd_manager } => {
+            let (_, denom) = resolve_pusd_manager(deps.storage, pusd_manager)
+                .map_err(|err| StdError::generic_err(err.to_string()))?;
+            to_json_binary(&deps.querier.query_balance(env.contract.address, denom)?)
+        }
+        QueryMsg::GetPusdManagers {} => {
+            let default = STATE.load(deps.storage)?.pusd_manager;
+            let pusd_managers = PUSD_MANAGERS
+                .range(deps.storage, None, None, Order::Ascending)
+                .map(|item| {
+                    item.map(|(pusd_manager, denom)| PusdManagerInfo {
+                        is_default: default.as_ref() == Some(&pusd_manager),
+                        pusd_manager,
+                        denom,
+                    })
+                })
+                .collect::<StdResult<Vec<PusdManagerInfo>>>()?;
+            to_json_binary(&pusd_managers)
+        }
+        QueryMsg::GetAllowlist { chain_id } => {
+            let recipients = ALLOWED_RECIPIENTS
+                .prefix(&chain_id)
+                .range(deps.storage, None, None, Order::Ascending)
+                .map(|item| {
+                    item.map(|(recipient, allowed)| AllowedRecipientInfo {
+                        recipient,
+                        added_at_height: allowed.added_at_height,
+                        active_from: allowed.active_from,
+                    })
+                })
+                .collect::<StdResult<Vec<AllowedRecipientInfo>>>()?;
+            to_json_binary(&AllowlistResponse {
+                enabled: allowlist_enabled(deps.storage, &env, &chain_id)?,
+                disabled_from: ALLOWLIST_DISABLED_FROM.may_load(deps.storage, &chain_id)?,
+                recipients,
+            })
+        }
+        QueryMsg::GetRateLimit { chain_id, denom } => {
+            let rate_limit =
+                RATE_LIMITS.load(deps.storage, (&chain_id, &rate_limit_denom(&denom)))?;
+            let used = rate_limit_used(&rate_limit, env.block.time.seconds());
+            to_json_binary(&RateLimitResponse {
+                max_amount: rate_limit.max_amount,
+                window: rate_limit.window,
+                used,
+                remaining: rate_limit.max_amount.saturating_sub(used),
+            })
+        }
     }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::{message_info, mock_dependencies, mock_env, MockApi};
+    use cosmwasm_std::{from_json, OwnedDeps, Timestamp};
+
+    fn migrate_msg() -> MigrateMsg {
+        MigrateMsg {
+            retry_delay: None,
+            allowlist_delay: None,
+            pusd_manager: None,
+            pusd_denom: None,
+        }
+    }
+
+    #[test]
+    fn migrates_deployed_layout() {
+        let mut deps = mock_dependencies();
+        let owner = deps.api.addr_make("owner");
+        // State as stored by 0.1.0, which never recorded a cw2 version
+        deps.storage.set(
+            b"state",
+            format!(r#"{{"owners":["{owner}"],"retry_delay":30}}"#).as_bytes(),
+        );
+        // the 0.1.0 layout lacks the fields added since
+        assert!(STATE.load(&deps.storage).is_err());
+
+        migrate(
+            deps.as_mut(),
+            mock_env(),
+            MigrateMsg {
+                allowlist_delay: Some(3600),
+                ..migrate_msg()
+            },
+        )
+        .unwrap();
+
+        let state = STATE.load(&deps.storage).unwrap();
+        assert_eq!(state.owners, vec![owner]);
+        assert_eq!(state.retry_delay, 30);
+        assert_eq!(state.allowlist_delay, 3600);
+        assert_eq!(state.pusd_manager, None);
+        assert!(!state.paused);
+        assert_eq!(state.pending_allowlist_delay, None);
+        let version = CONTRACT.load(&deps.storage).unwrap();
+        assert_eq!(version.contract, CONTRACT_NAME);
+        assert_eq!(version.version, CONTRACT_VERSION);
+    }
+
+    #[test]
+    fn refuses_downgrade() {
+        let mut deps = mock_dependencies();
+        set_contract_version(&mut deps.storage, CONTRACT_NAME, "99.0.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), migrate_msg()).unwrap_err();
+        assert!(matches!(err, ContractError::CannotDowngrade { .. }));
+    }
+
+    #[test]
+    fn refuses_other_contract() {
+        let mut deps = mock_dependencies();
+        set_contract_version(
+            &mut deps.storage,
+            "crates.io:token-purchaser-collector-cw",
+            "0.1.0",
+        )
+        .unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), migrate_msg()).unwrap_err();
+        assert!(matches!(err, ContractError::WrongContract { .. }));
+    }
+
+    fn env_at(seconds: u64) -> Env {
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(seconds);
+        env
+    }
+
+    #[test]
+    fn rate_limit_usage_decays_over_window() {
+        let rate_limit = RateLimit {
+            max_amount: Uint128::new(1000),
+            window: 100,
+            used: Uint128::new(800),
+            updated_at: 1000,
+        };
+        assert_eq!(rate_limit_used(&rate_limit, 1000), Uint128::new(800));
+        assert_eq!(rate_limit_used(&rate_limit, 1025), Uint128::new(550));
+        assert_eq!(rate_limit_used(&rate_limit, 1080), Uint128::zero());
+        assert_eq!(rate_limit_used(&rate_limit, 5000), Uint128::zero());
+        // a clock behind the last update does not decay anything
+        assert_eq!(rate_limit_used(&rate_limit, 900), Uint128::new(800));
+    }
+
+    #[test]
+    fn consume_rate_limit_counts_outflows() {
+        let mut deps = mock_dependencies();
+        // denoms without a limit are not counted
+        consume_rate_limit(
+            &mut deps.storage,
+            &env_at(1000),
+            "chain",
+            "denom",
+            Uint128::MAX,
+        )
+        .unwrap();
+
+        save_rate_limit(
+            &mut deps.storage,
+            "chain",
+            "denom",
+            Uint128::new(1000),
+            100,
+            false,
+        )
+        .unwrap();
+        consume_rate_limit(
+            &mut deps.storage,
+            &env_at(1000),
+            "chain",
+            "denom",
+            Uint128::new(600),
+        )
+        .unwrap();
+        let err = consume_rate_limit(
+            &mut deps.storage,
+            &env_at(1000),
+            "chain",
+            "denom",
+            Uint128::new(500),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::RateLimitExceeded { remaining, .. } if remaining == Uint128::new(400)
+        ));
+
+        // half a window later 500 of the 600 used has decayed
+        consume_rate_limit(
+            &mut deps.storage,
+            &env_at(1050),
+            "chain",
+            "denom",
+            Uint128::new(900),
+        )
+        .unwrap();
+        let rate_limit = RATE_LIMITS.load(&deps.storage, ("chain", "denom")).unwrap();
+        assert_eq!(rate_limit.used, Uint128::new(1000));
+        assert_eq!(rate_limit.updated_at, 1050);
+        let err = consume_rate_limit(
+            &mut deps.storage,
+            &env_at(1050),
+            "chain",
+            "denom",
+            Uint128::one(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::RateLimitExceeded { .. }));
+    }
+
+    #[test]
+    fn owners_can_only_tighten_rate_limits() {
+        let mut deps = mock_dependencies();
+        save_rate_limit(
+            &mut deps.storage,
+            "chain",
+            "denom",
+            Uint128::new(1000),
+            100,
+            false,
+        )
+        .unwrap();
+        consume_rate_limit(
+            &mut deps.storage,
+            &env_at(1000),
+            "chain",
+            "denom",
+            Uint128::new(300),
+        )
+        .unwrap();
+
+        save_rate_limit(
+            &mut deps.storage,
+            "chain",
+            "denom",
+            Uint128::new(500),
+            200,
+            false,
+        )
+        .unwrap();
+        let rate_limit = RATE_LIMITS.load(&deps.storage, ("chain", "denom")).unwrap();
+        assert_eq!(rate_limit.used, Uint128::new(300));
+
+        let err = save_rate_limit(
+            &mut deps.storage,
+            "chain",
+            "denom",
+            Uint128::new(501),
+            200,
+            false,
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::RateLimitLoosened {}));
+        let err = save_rate_limit(
+            &mut deps.storage,
+            "chain",
+            "denom",
+            Uint128::new(500),
+            199,
+            false,
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::RateLimitLoosened {}));
+
+        save_rate_limit(
+            &mut deps.storage,
+            "chain",
+            "denom",
+            Uint128::new(5000),
+            10,
+            true,
+        )
+        .unwrap();
+        let rate_limit = RATE_LIMITS.load(&deps.storage, ("chain", "denom")).unwrap();
+        assert_eq!(rate_limit.max_amount, Uint128::new(5000));
+    }
+
+    fn reply_with_events(id: u64, events: Vec<Event>) -> Reply {
+        #[allow(deprecated)]
+        let response = cosmwasm_std::SubMsgResponse {
+            events,
+            data: None,
+            msg_responses: vec![],
+        };
+        Reply {
+            id,
+            payload: Binary::default(),
+            gas_used: 0,
+            result: cosmwasm_std::SubMsgResult::Ok(response),
+        }
+    }
+
+    #[test]
+    fn parses_withdrawal_nonces() {
+        let deps = mock_dependencies();
+        let pusd_manager = deps.api.addr_make("pusd_manager");
+        let other = deps.api.addr_make("other");
+        let event = |contract: &Addr, nonce: &str| {
+            Event::new("wasm")
+                .add_attribute("_contract_address", contract.as_str())
+                .add_attribute("nonce", nonce)
+        };
+        assert_eq!(
+            parse_withdrawal_nonce(&[event(&pusd_manager, "5")], &pusd_manager),
+            Some(5)
+        );
+        // nonces emitted by other contracts are ignored
+        assert_eq!(
+            parse_withdrawal_nonce(
+                &[event(&other, "3"), event(&pusd_manager, "8")],
+                &pusd_manager
+            ),
+            Some(8)
+        );
+        assert_eq!(
+            parse_withdrawal_nonce(&[event(&other, "3")], &pusd_manager),
+            None
+        );
+        assert_eq!(
+            parse_withdrawal_nonce(&[event(&pusd_manager, "abc")], &pusd_manager),
+            None
+        );
+    }
+
+    #[test]
+    fn withdrawals_can_be_resolved_registered_and_completed() {
+        let mut deps = mock_dependencies();
+        let owner = deps.api.addr_make("owner");
+        let pusd_manager = deps.api.addr_make("pusd_manager");
+        let info = message_info(&owner, &[]);
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            InstantiateMsg {
+                retry_delay: 0,
+                owners: vec![owner.to_string()],
+            },
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            ExecuteMsg::SetPusdManager {
+                pusd_manager: pusd_manager.to_string(),
+                denom: None,
+                is_default: true,
+            },
+        )
+        .unwrap();
+        let outstanding = |deps: &OwnedDeps<_, _, _>| -> Vec<u64> {
+            let withdrawals: Vec<PusdWithdrawal> = from_json(
+                query(
+                    deps.as_ref(),
+                    mock_env(),
+                    QueryMsg::GetOutstandingPusdWithdrawals {
+                        pusd_manager: None,
+                        start_after: None,
+                        limit: None,
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap();
+            withdrawals
+                .iter()
+                .map(|withdrawal| withdrawal.nonce)
+                .collect()
+        };
+
+        // a reply without a nonce keeps the withdrawal as unresolved
+        PENDING_WITHDRAWALS
+            .push_back(
+                &mut deps.storage,
+                &PendingWithdrawal {
+                    pusd_manager: pusd_manager.clone(),
+                    chain_id: "chain".to_string(),
+                    recipient: "0xrecipient".to_string(),
+                    amount: Uint128::new(100),
+                },
+            )
+            .unwrap();
+        reply(
+            deps.as_mut(),
+            mock_env(),
+            reply_with_events(PUSD_WITHDRAW_REPLY_ID, vec![]),
+        )
+        .unwrap();
+        assert!(UNRESOLVED_WITHDRAWALS.has(&deps.storage, 1));
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            ExecuteMsg::ResolveWithdrawPusd { id: 1, nonce: 4 },
+        )
+        .unwrap();
+        assert!(!UNRESOLVED_WITHDRAWALS.has(&deps.storage, 1));
+
+        // a withdrawal made before the upgrade
+        let register = ExecuteMsg::RegisterWithdrawPusd {
+            pusd_manager: None,
+            nonce: 2,
+            chain_id: "chain".to_string(),
+            recipient: "0xrecipient".to_string(),
+            amount: Uint128::new(50),
+        };
+        execute(deps.as_mut(), mock_env(), info.clone(), register.clone()).unwrap();
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), register).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::WithdrawalAlreadyRecorded { nonce: 2 }
+        ));
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            ExecuteMsg::ReWithdrawPusd {
+                pusd_manager: None,
+                nonce: 2,
+            },
+        )
+        .unwrap();
+        assert_eq!(outstanding(&deps), vec![2, 4]);
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            ExecuteMsg::CompleteWithdrawPusd {
+                pusd_manager: None,
+                nonce: 4,
+            },
+        )
+        .unwrap();
+        assert_eq!(outstanding(&deps), vec![2]);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::CancelWithdrawPusd {
+                pusd_manager: None,
+                nonce: 4,
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::WithdrawalNotPending { nonce: 4 }
+        ));
+    }
+
+    #[test]
+    fn rate_limits_match_mixed_case_denoms() {
+        let mut deps = mock_dependencies();
+        let owner = deps.api.addr_make("owner");
+        let pusd_manager = deps.api.addr_make("pusd_manager");
+        let pusd_denom = format!("factory/{pusd_manager}/uPUSD");
+        let info = message_info(&owner, &[]);
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            InstantiateMsg {
+                retry_delay: 0,
+                owners: vec![owner.to_string()],
+            },
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            ExecuteMsg::SetPusdManager {
+                pusd_manager: pusd_manager.to_string(),
+                denom: Some(pusd_denom.clone()),
+                is_default: true,
+            },
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            ExecuteMsg::SetRateLimit {
+                chain_id: "chain".to_string(),
+                denom: pusd_denom.clone(),
+                max_amount: Uint128::new(100),
+                window: 3600,
+            },
+        )
+        .unwrap();
+        let withdraw = |amount: u128| ExecuteMsg::WithdrawPusd {
+            pusd_manager: None,
+            chain_id: "chain".to_string(),
+            recipient: "0xrecipient".to_string(),
+            amount: Uint128::new(amount),
+        };
+        execute(deps.as_mut(), mock_env(), info.clone(), withdraw(60)).unwrap();
+        let err = execute(deps.as_mut(), mock_env(), info, withdraw(50)).unwrap_err();
+        assert!(matches!(err, ContractError::RateLimitExceeded { .. }));
+
+        let rate_limit: RateLimitResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::GetRateLimit {
+                    chain_id: "chain".to_string(),
+                    denom: pusd_denom,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(rate_limit.remaining, Uint128::new(40));
+    }
+
+    fn native(denom: &str) -> AssetInfo {
+        AssetInfo::NativeToken {
+            denom: denom.to_string(),
+        }
+    }
+
+    fn hop(offer: &str, ask: &str) -> SwapOperation {
+        SwapOperation::AstroSwap {
+            offer_asset_info: native(offer),
+            ask_asset_info: native(ask),
+        }
+    }
+
+    #[test]
+    fn routes_must_be_connected() {
+        let route = [
+            hop("ugrain", "uatom"),
+            hop("uatom", "uosmo"),
+            hop("uusdc", "upusd"),
+        ];
+        let err = validate_route(&route, &[Coin::new(100u128, "ugrain")]).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::BrokenRoute { index: 2, ref offer, ref ask }
+                if offer == "uusdc" && ask == "uosmo"
+        ));
+        let err = validate_route(&[], &[Coin::new(100u128, "ugrain")]).unwrap_err();
+        assert!(matches!(err, ContractError::EmptyRoute {}));
+        let long_route = vec![hop("ugrain", "ugrain"); MAX_SWAP_OPERATIONS + 1];
+        let err = validate_route(&long_route, &[Coin::new(100u128, "ugrain")]).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::TooManyHops { hops, max }
+                if hops == MAX_SWAP_OPERATIONS + 1 && max == MAX_SWAP_OPERATIONS
+        ));
+        validate_route(&route[..2], &[Coin::new(100u128, "ugrain")]).unwrap();
+    }
+
+    #[test]
+    fn funds_must_match_the_first_offer() {
+        let route = [hop("ugrain", "upusd")];
+        for funds in [
+            vec![],
+            vec![Coin::new(100u128, "uatom")],
+            vec![Coin::new(0u128, "ugrain")],
+            vec![Coin::new(100u128, "ugrain"), Coin::new(100u128, "uatom")],
+        ] {
+            let err = validate_route(&route, &funds).unwrap_err();
+            assert!(matches!(
+                err,
+                ContractError::FundsMismatch { ref expected } if expected == "ugrain"
+            ));
+        }
+
+        // a route offering a CW20 token cannot be paid with native funds
+        let token = MockApi::default().addr_make("token");
+        let route = [SwapOperation::AstroSwap {
+            offer_asset_info: AssetInfo::Token {
+                contract_addr: token.clone(),
+            },
+            ask_asset_info: native("upusd"),
+        }];
+        let err = validate_route(&route, &[Coin::new(100u128, "ugrain")]).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::FundsMismatch { ref expected } if expected == token.as_str()
+        ));
+    }
+}
@@ -1,10 +1,75 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{StdError, Uint128};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum ContractError {
     #[error("{0}")]
     Std(#[from] StdError),
+
+    #[error("Swap route is empty")]
+    EmptyRoute {},
+
+    #[error("Swap route has {hops} operations, the maximum is {max}")]
+    TooManyHops { hops: usize, max: usize },
+
+    #[error("Broken swap route at index {index}: offer asset {offer} does not match previous ask asset {ask}")]
+    BrokenRoute {
+        index: usize,
+        offer: String,
+        ask: String,
+    },
+
+    #[error("Funds must be a single non-zero coin of the first offer asset {expected}")]
+    FundsMismatch { expected: String },
+
+    #[error("Rate limit window must be positive")]
+    InvalidRateLimit {},
+
+    #[error("Loosening or removing a rate limit requires sudo")]
+    RateLimitLoosened {},
+
+    #[error("Rate limit exceeded for {denom} to {chain_id}, remaining {remaining}")]
+    RateLimitExceeded {
+        chain_id: String,
+        denom: String,
+        remaining: Uint128,
+    },
+
+    #[error("Recipient {recipient} is not allowed on {chain_id}")]
+    RecipientNotAllowed { chain_id: String, recipient: String },
+
+    #[error("No default pusd_manager is configured")]
+    NoDefaultPusdManager {},
+
+    #[error("pusd_manager {pusd_manager} is not registered")]
+    UnregisteredPusdManager { pusd_manager: String },
+
+    #[error("PUSD withdrawal {nonce} is not recorded")]
+    UnknownWithdrawal { nonce: u64 },
+
+    #[error("PUSD withdrawal {nonce} is not pending")]
+    WithdrawalNotPending { nonce: u64 },
+
+    #[error("PUSD withdrawal {nonce} is already recorded")]
+    WithdrawalAlreadyRecorded { nonce: u64 },
+
+    #[error("Unresolved withdrawal {id} is not recorded")]
+    UnknownUnresolvedWithdrawal { id: u64 },
+
+    #[error("Unknown reply id {id}")]
+    UnknownReplyId { id: u64 },
+
+    #[error("{0}")]
+    Semver(#[from] semver::Error),
+
+    #[error("Cannot migrate {actual} to {expected}")]
+    WrongContract { expected: String, actual: String },
+
+    #[error("Cannot downgrade from {stored} to {version}")]
+    CannotDowngrade { stored: String, version: String },
+
+    #[error("Contract is paused")]
+    Paused {},
     // Add any other custom errors you like here.
     // Look at https://docs.rs/thiserror/1.0.21/thiserror/ for details.
 }
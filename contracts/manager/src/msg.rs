@@ -1,5 +1,7 @@
+use std::fmt;
+
 #[allow(unused_imports)]
-use crate::state::{ChainSetting, State};
+use crate::state::{ChainSetting, PusdWithdrawal, State, UnresolvedWithdrawal};
 use cosmwasm_schema::{cw_serde, QueryResponses};
 use cosmwasm_std::{Addr, Binary, Coin, CustomMsg, Decimal, Uint128, Uint256};
 
@@ -35,8 +37,8 @@ pub enum ExecuteMsg {
         nonce: Uint128,
     },
     WithdrawPusd {
-        /// The address of the pusd_manager CW contract
-        pusd_manager: Addr,
+        /// The address of a registered pusd_manager CW contract, defaults to the configured one
+        pusd_manager: Option<Addr>,
         /// The chain id of the chain to withdraw from
         chain_id: String,
         /// The EVM address to send the funds to
@@ -45,17 +47,53 @@ pub enum ExecuteMsg {
         amount: Uint128,
     },
     ReWithdrawPusd {
-        /// The address of the pusd_manager CW contract
-        pusd_manager: Addr,
+        /// The address of a registered pusd_manager CW contract, defaults to the configured one
+        pusd_manager: Option<Addr>,
         /// The nonce of the withdrawal to re-withdraw
         nonce: u64,
     },
     CancelWithdrawPusd {
-        /// The address of the pusd_manager CW contract
-        pusd_manager: Addr,
+        /// The address of a registered pusd_manager CW contract, defaults to the configured one
+        pusd_manager: Option<Addr>,
         /// The nonce of the withdrawal to cancel
         nonce: u64,
     },
+    /// Marks a pending withdrawal as completed once it has arrived on the target chain
+    CompleteWithdrawPusd {
+        /// The address of a registered pusd_manager CW contract, defaults to the configured one
+        pusd_manager: Option<Addr>,
+        /// The nonce of the withdrawal to complete
+        nonce: u64,
+    },
+    /// Records a pending withdrawal the contract does not know of, such as one made before
+    /// withdrawals were recorded, so that it can be re-withdrawn, cancelled or completed
+    RegisterWithdrawPusd {
+        /// The address of a registered pusd_manager CW contract, defaults to the configured one
+        pusd_manager: Option<Addr>,
+        /// The nonce the pusd_manager assigned to the withdrawal
+        nonce: u64,
+        /// The chain id of the chain withdrawn from
+        chain_id: String,
+        /// The EVM address the funds are sent to
+        recipient: String,
+        /// The PUSD amount withdrawn
+        amount: Uint128,
+    },
+    /// Records an unresolved withdrawal under the nonce the pusd_manager assigned to it
+    ResolveWithdrawPusd {
+        id: u64,
+        nonce: u64,
+    },
+    /// Registers a pusd_manager CW contract, optionally as the default one
+    SetPusdManager {
+        pusd_manager: String,
+        /// The PUSD denom, defaults to factory/<pusd_manager>/upusd
+        denom: Option<String>,
+        is_default: bool,
+    },
+    RemovePusdManager {
+        pusd_manager: String,
+    },
     SetChainSetting {
         chain_id: String,
         compass_job_id: String,
@@ -84,8 +122,35 @@ pub enum ExecuteMsg {
         chain_id: String,
         new_service_fee: Uint256,
     },
+    /// Adds or tightens a rate limit. Raising `max_amount` or shortening `window` requires
+    /// `SudoMsg::SetRateLimit`.
+    SetRateLimit {
+        chain_id: String,
+        /// The EVM token address, or the PUSD denom for `WithdrawPusd`
+        denom: String,
+        /// The maximum amount sent out within one window
+        max_amount: Uint128,
+        /// The window length in seconds
+        window: u64,
+    },
+    /// Enforces or lifts the recipient allowlist of a chain
+    SetAllowlistEnabled {
+        chain_id: String,
+        enabled: bool,
+    },
+    /// Allows transfers to an EVM address once the allowlist delay has passed
+    AddAllowedRecipient {
+        chain_id: String,
+        recipient: String,
+    },
+    RemoveAllowedRecipient {
+        chain_id: String,
+        recipient: String,
+    },
     UpdateConfig {
         retry_delay: Option<u64>,
+        /// The delay in seconds before a new allowlisted recipient can receive funds
+        allowlist_delay: Option<u64>,
     },
     AddOwner {
         owners: Vec<String>,
@@ -96,7 +161,16 @@ pub enum ExecuteMsg {
 }
 
 #[cw_serde]
-pub struct MigrateMsg {}
+pub struct MigrateMsg {
+    /// Replaces the retry delay
+    pub retry_delay: Option<u64>,
+    /// Replaces the allowlist delay
+    pub allowlist_delay: Option<u64>,
+    /// Registers a pusd_manager as the default one
+    pub pusd_manager: Option<String>,
+    /// The PUSD denom of `pusd_manager`, defaults to factory/<pusd_manager>/upusd
+    pub pusd_denom: Option<String>,
+}
 
 #[cw_serde]
 pub enum SwapOperation {
@@ -108,6 +182,22 @@ pub enum SwapOperation {
     },
 }
 
+impl SwapOperation {
+    pub fn offer_asset_info(&self) -> &AssetInfo {
+        match self {
+            SwapOperation::AstroSwap {
+                offer_asset_info, ..
+            } => offer_asset_info,
+        }
+    }
+
+    pub fn ask_asset_info(&self) -> &AssetInfo {
+        match self {
+            SwapOperation::AstroSwap { ask_asset_info, .. } => ask_asset_info,
+        }
+    }
+}
+
 #[cw_serde]
 #[derive(Hash, Eq)]
 pub enum AssetInfo {
@@ -117,6 +207,43 @@ pub enum AssetInfo {
     NativeToken { denom: String },
 }
 
+impl fmt::Display for AssetInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssetInfo::Token { contract_addr } => write!(f, "{contract_addr}"),
+            AssetInfo::NativeToken { denom } => write!(f, "{denom}"),
+        }
+    }
+}
+
+/// Messages only chain governance can send
+#[cw_serde]
+pub enum SudoMsg {
+    /// Replaces every owner
+    SetOwners {
+        owners: Vec<String>,
+    },
+    /// Rejects every execute message until `Unpause`
+    Pause {},
+    Unpause {},
+    /// Cancels a PUSD withdrawal, including withdrawals the contract did not record
+    CancelWithdrawPusd {
+        pusd_manager: Addr,
+        nonce: u64,
+    },
+    /// Sets a rate limit, including limits looser than the current one
+    SetRateLimit {
+        chain_id: String,
+        denom: String,
+        max_amount: Uint128,
+        window: u64,
+    },
+    RemoveRateLimit {
+        chain_id: String,
+        denom: String,
+    },
+}
+
 #[cw_serde]
 pub enum ExternalExecuteMsg {
     ExecuteSwapOperations {
@@ -139,6 +266,12 @@ pub enum ExternalExecuteMsg {
     },
 }
 
+#[cw_serde]
+pub enum ExternalQueryMsg {
+    /// pusd_manager query for a withdrawal by nonce
+    GetWithdrawal { nonce: u64 },
+}
+
 #[cw_serde]
 pub enum PalomaMsg {
     /// Message struct for cross-chain calls.
@@ -158,6 +291,74 @@ pub enum QueryMsg {
     GetState {},
     #[returns(ChainSetting)]
     GetChainSetting { chain_id: String },
+    #[returns(Vec<PusdWithdrawal>)]
+    GetOutstandingPusdWithdrawals {
+        /// Defaults to the configured pusd_manager
+        pusd_manager: Option<Addr>,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(Vec<UnresolvedWithdrawal>)]
+    GetUnresolvedPusdWithdrawals {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(PusdManagerWithdrawal)]
+    GetPusdWithdrawal {
+        /// Defaults to the configured pusd_manager
+        pusd_manager: Option<Addr>,
+        nonce: u64,
+    },
+    #[returns(Coin)]
+    GetPusdBalance {
+        /// Defaults to the configured pusd_manager
+        pusd_manager: Option<Addr>,
+    },
+    #[returns(Vec<PusdManagerInfo>)]
+    GetPusdManagers {},
+    #[returns(AllowlistResponse)]
+    GetAllowlist { chain_id: String },
+    #[returns(RateLimitResponse)]
+    GetRateLimit { chain_id: String, denom: String },
+}
+
+#[cw_serde]
+pub struct RateLimitResponse {
+    pub max_amount: Uint128,
+    pub window: u64,
+    pub used: Uint128,
+    pub remaining: Uint128,
+}
+
+#[cw_serde]
+pub struct AllowlistResponse {
+    pub enabled: bool,
+    /// When a requested disable takes effect
+    pub disabled_from: Option<u64>,
+    pub recipients: Vec<AllowedRecipientInfo>,
+}
+
+#[cw_serde]
+pub struct AllowedRecipientInfo {
+    pub recipient: String,
+    pub added_at_height: u64,
+    pub active_from: u64,
+}
+
+/// A withdrawal as the pusd_manager reports it
+#[cw_serde]
+pub struct PusdManagerWithdrawal {
+    pub nonce: u64,
+    pub chain_id: String,
+    pub recipient: String,
+    pub amount: Uint128,
+}
+
+#[cw_serde]
+pub struct PusdManagerInfo {
+    pub pusd_manager: Addr,
+    pub denom: String,
+    pub is_default: bool,
 }
 
 impl CustomMsg for PalomaMsg {}
@@ -1,6 +1,6 @@
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::Addr;
-use cw_storage_plus::{Item, Map};
+use cosmwasm_std::{Addr, Uint128};
+use cw_storage_plus::{Deque, Item, Map};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
@@ -8,6 +8,26 @@ use serde::{Deserialize, Serialize};
 pub struct State {
     pub owners: Vec<Addr>,
     pub retry_delay: u64,
+    pub allowlist_delay: u64,
+    /// The pusd_manager used when a call does not name one
+    pub pusd_manager: Option<Addr>,
+    /// Set by governance to reject every execute message
+    pub paused: bool,
+    /// A lower `allowlist_delay` waiting out the delay it replaces
+    pub pending_allowlist_delay: Option<PendingAllowlistDelay>,
+}
+
+/// `State` as stored by 0.1.0
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct StateV0_1 {
+    pub owners: Vec<Addr>,
+    pub retry_delay: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct PendingAllowlistDelay {
+    pub allowlist_delay: u64,
+    pub effective_at: u64,
 }
 
 #[cw_serde]
@@ -16,5 +36,76 @@ pub struct ChainSetting {
     pub main_job_id: String,
 }
 
+#[cw_serde]
+pub struct PusdWithdrawal {
+    pub pusd_manager: Addr,
+    pub nonce: u64,
+    pub chain_id: String,
+    pub recipient: String,
+    pub amount: Uint128,
+    pub status: WithdrawalStatus,
+}
+
+#[cw_serde]
+pub enum WithdrawalStatus {
+    Pending,
+    Cancelled,
+    /// Arrived on the target chain, as confirmed by an owner
+    Completed,
+}
+
+/// A PUSD withdrawal dispatched as a submessage, waiting for its nonce.
+#[cw_serde]
+pub struct PendingWithdrawal {
+    pub pusd_manager: Addr,
+    pub chain_id: String,
+    pub recipient: String,
+    pub amount: Uint128,
+}
+
+/// A PUSD withdrawal whose reply carried no nonce. It stays here until an owner resolves
+/// it with the nonce the pusd_manager assigned.
+#[cw_serde]
+pub struct UnresolvedWithdrawal {
+    pub id: u64,
+    pub pusd_manager: Addr,
+    pub chain_id: String,
+    pub recipient: String,
+    pub amount: Uint128,
+}
+
+#[cw_serde]
+pub struct AllowedRecipient {
+    pub added_at_height: u64,
+    pub active_from: u64,
+}
+
+/// Rolling-window outflow limit. `used` decays linearly over `window` seconds.
+#[cw_serde]
+pub struct RateLimit {
+    pub max_amount: Uint128,
+    pub window: u64,
+    pub used: Uint128,
+    pub updated_at: u64,
+}
+
 pub const CHAIN_SETTINGS: Map<String, ChainSetting> = Map::new("chain_settings");
 pub const STATE: Item<State> = Item::new("state");
+pub const STATE_V0_1: Item<StateV0_1> = Item::new("state");
+// (chain id, token) => outflow rate limit
+pub const RATE_LIMITS: Map<(&str, &str), RateLimit> = Map::new("rate_limits");
+// chain id => recipient allowlist enforced
+pub const ALLOWLIST_ENABLED: Map<&str, bool> = Map::new("allowlist_enabled");
+// chain id => time the allowlist stops being enforced, once the allowlist delay has passed
+pub const ALLOWLIST_DISABLED_FROM: Map<&str, u64> = Map::new("allowlist_disabled_from");
+// (chain id, lowercase EVM address) => allowed recipient
+pub const ALLOWED_RECIPIENTS: Map<(&str, &str), AllowedRecipient> = Map::new("allowed_recipients");
+// registered pusd_manager => PUSD denom
+pub const PUSD_MANAGERS: Map<&Addr, String> = Map::new("pusd_managers");
+// (pusd_manager, nonce) => withdrawal
+pub const PUSD_WITHDRAWALS: Map<(&Addr, u64), PusdWithdrawal> = Map::new("pusd_withdrawals");
+// replies arrive in dispatch order, so pending withdrawals are matched first in, first out
+pub const PENDING_WITHDRAWALS: Deque<PendingWithdrawal> = Deque::new("pending_withdrawals");
+pub const UNRESOLVED_WITHDRAWALS: Map<u64, UnresolvedWithdrawal> =
+    Map::new("unresolved_withdrawals");
+pub const UNRESOLVED_WITHDRAWAL_COUNT: Item<u64> = Item::new("unresolved_withdrawal_count");